@@ -0,0 +1,24 @@
+// Blocks in the binary encoding, as peers send them over the wire protocol. Whatever
+// decodes has to survive validation and encode back to the same block.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate tulipchain;
+
+use tulipchain::blockchain::{Block, Blockchain};
+use tulipchain::spec::ChainSpec;
+
+fuzz_target!(|data: &[u8]| {
+    let block = match Block::decode(data) {
+        Ok(block) => block,
+        Err(_) => return,
+    };
+
+    assert!(Block::decode(&block.encode()).unwrap() == block);
+
+    let spec = ChainSpec::dev();
+    let _ = block.validate_body(spec.reward);
+    let genesis = Blockchain::with_spec(spec.clone()).chain[0].header.clone();
+    let _ = Blockchain::validate_header_chain(Some(&genesis), &[block.header], spec.difficulty);
+});
@@ -0,0 +1,24 @@
+// Blocks as announced by peers that don't speak the wire protocol, which go through
+// the same validation as the binary ones
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate serde_json;
+extern crate tulipchain;
+
+use tulipchain::blockchain::{Block, Blockchain};
+
+fuzz_target!(|data: &[u8]| {
+    let block: Block = match serde_json::from_slice(data) {
+        Ok(block) => block,
+        Err(_) => return,
+    };
+
+    let mut chain = Blockchain::new();
+    let _ = chain.accept_block(block.clone());
+
+    let json = serde_json::to_string(&block).unwrap();
+    assert!(serde_json::from_str::<Block>(&json).unwrap() == block);
+    assert!(Block::decode(&block.encode()).unwrap() == block);
+});
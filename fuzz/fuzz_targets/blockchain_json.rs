@@ -0,0 +1,26 @@
+// Whole chains as served on /blockchain/full, validated the way a snapshot is before a
+// node switches to it
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate serde_json;
+extern crate tulipchain;
+
+use tulipchain::blockchain::Blockchain;
+use tulipchain::spec::ChainSpec;
+
+fuzz_target!(|data: &[u8]| {
+    let received: Blockchain = match serde_json::from_slice(data) {
+        Ok(received) => received,
+        Err(_) => return,
+    };
+
+    for transaction in &received.pending_transactions {
+        let _ = transaction.verify_digest();
+    }
+
+    let chain = Blockchain::from_blocks(ChainSpec::dev(), received.chain);
+    let _ = chain.validate_chain();
+    let _ = chain.cumulative_work();
+});
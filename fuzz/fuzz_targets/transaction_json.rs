@@ -0,0 +1,23 @@
+// Transactions as submitted to the http api and relayed by peers. Whatever parses has
+// to survive verification and round-trip through JSON.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate serde_json;
+extern crate tulipchain;
+
+use tulipchain::transaction::Transaction;
+
+fuzz_target!(|data: &[u8]| {
+    let transaction: Transaction = match serde_json::from_slice(data) {
+        Ok(transaction) => transaction,
+        Err(_) => return,
+    };
+
+    let _ = transaction.verify_digest();
+    let _ = transaction.id();
+
+    let json = serde_json::to_string(&transaction).unwrap();
+    assert!(serde_json::from_str::<Transaction>(&json).unwrap() == transaction);
+});
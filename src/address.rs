@@ -1,46 +1,183 @@
+use sodiumoxide::crypto::hash::sha256;
 use sodiumoxide::crypto::sign;
+use super::base58;
+use super::keys::{KeySource, OsKeys};
 use super::{PrivateKey, PublicKey, Tulips};
 
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
 use transaction::Transaction;
 
+// Leads every encoded address, so that the format can change without old addresses
+// being mistaken for new ones
+pub const ADDRESS_VERSION: u8 = 0x41;
+
+// Bytes of the double sha256 digest appended to an encoded address
+const CHECKSUM_BYTES: usize = 4;
+
+#[derive(Debug, PartialEq)]
+pub enum AddressError {
+    // The address holds a character outside of the base58 alphabet
+    Encoding,
+    // The address decodes to the wrong number of bytes
+    Length(usize),
+    // The address was encoded in a format we don't know
+    Version(u8),
+    // The checksum doesn't match, most likely because of a typo
+    Checksum,
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AddressError::Encoding => write!(f, "the address isn't valid base58"),
+            AddressError::Length(len) => write!(f, "an address can't be {} bytes long", len),
+            AddressError::Version(version) => write!(f, "unknown address version {}", version),
+            AddressError::Checksum => write!(f, "the address' checksum doesn't match"),
+        }
+    }
+}
+
+// A keypair. What the address holds is up to the ledger, see Blockchain::balance.
 #[derive(Debug, Clone)]
 pub struct Address {
     pub public_key: PublicKey,
     private_key: PrivateKey,
-
-    pub balance: Tulips,
 }
 
 impl Address {
     pub fn new() -> Address {
-        let (public_key, private_key) = sign::gen_keypair();
+        Address::generate(&mut OsKeys)
+    }
+
+    // Creates an address with the next keypair of the source
+    pub fn generate<K: KeySource + ?Sized>(keys: &mut K) -> Address {
+        let (public_key, private_key) = keys.keypair();
 
         Address {
             public_key,
             private_key,
+        }
+    }
 
-            balance: 0,
+    // Creates the address belonging to a seed, which always results in the same keypair
+    pub fn from_seed(seed: &sign::Seed) -> Address {
+        let (public_key, private_key) = sign::keypair_from_seed(seed);
+
+        Address {
+            public_key,
+            private_key,
         }
     }
 
-    // Returns a signed transaction, assuming the sender has enough tulips
+    // Returns a signed transaction carrying the given sequence number. Whether the
+    // address can afford it, and whether the number is its next one, is checked when
+    // the transaction is added to the blockchain.
     pub fn new_transaction(
-        &mut self,
+        &self,
         value: Tulips,
         recipient_addr: PublicKey,
-    ) -> Option<Transaction> {
-        // Transactions should still be forged easily, so this is verified again before
-        // the transaction is added to the blockchain
-        if self.balance < value {
-            return None;
-        }
+        sequence: u64,
+    ) -> Transaction {
+        let mut transaction =
+            Transaction::new(Some(self.public_key), recipient_addr, value, sequence);
+        transaction.sign(&self.private_key);
 
-        let mut transaction = Transaction::new(Some(self.public_key), recipient_addr, value);
+        transaction
+    }
+}
 
-        // Sign the transaction and update the balance
-        transaction.sign(&self.private_key);
-        self.balance -= value;
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", encode(&self.public_key))
+    }
+}
+
+fn checksum(payload: &[u8]) -> Vec<u8> {
+    let sha256::Digest(ref once) = sha256::hash(payload);
+    let sha256::Digest(ref twice) = sha256::hash(once);
 
-        Some(transaction)
+    twice[..CHECKSUM_BYTES].to_vec()
+}
+
+// Writes a public key the way users get to see it: base58 over the version byte, the
+// key and a checksum
+pub fn encode(public_key: &PublicKey) -> String {
+    let mut payload = vec![ADDRESS_VERSION];
+    payload.extend_from_slice(&public_key[..]);
+
+    let checksum = checksum(&payload);
+    payload.extend(checksum);
+
+    base58::encode(&payload)
+}
+
+pub fn decode(encoded: &str) -> Result<PublicKey, AddressError> {
+    let bytes = base58::decode(encoded.trim()).ok_or(AddressError::Encoding)?;
+    if bytes.len() != 1 + sign::PUBLICKEYBYTES + CHECKSUM_BYTES {
+        return Err(AddressError::Length(bytes.len()));
+    }
+
+    let (payload, expected) = bytes.split_at(bytes.len() - CHECKSUM_BYTES);
+    if checksum(payload) != expected {
+        return Err(AddressError::Checksum);
+    }
+
+    if payload[0] != ADDRESS_VERSION {
+        return Err(AddressError::Version(payload[0]));
+    }
+
+    Ok(PublicKey::from_slice(&payload[1..]).unwrap())
+}
+
+// Use with `#[serde(with = "address")]`. Binary formats keep the key's bytes as they
+// are, so the block encoding isn't affected.
+pub fn serialize<S: Serializer>(public_key: &PublicKey, serializer: S) -> Result<S::Ok, S::Error> {
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&encode(public_key))
+    } else {
+        public_key.serialize(serializer)
+    }
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PublicKey, D::Error> {
+    if deserializer.is_human_readable() {
+        let encoded = String::deserialize(deserializer)?;
+        decode(&encoded).map_err(de::Error::custom)
+    } else {
+        PublicKey::deserialize(deserializer)
+    }
+}
+
+// Use with `#[serde(with = "address::option")]` on optional keys
+pub mod option {
+    use super::super::PublicKey;
+    use super::{decode, encode};
+
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S>(public_key: &Option<PublicKey>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            public_key.as_ref().map(encode).serialize(serializer)
+        } else {
+            public_key.serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<PublicKey>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            match Option::<String>::deserialize(deserializer)? {
+                Some(encoded) => decode(&encoded).map(Some).map_err(de::Error::custom),
+                None => Ok(None),
+            }
+        } else {
+            Option::<PublicKey>::deserialize(deserializer)
+        }
     }
 }
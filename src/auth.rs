@@ -0,0 +1,35 @@
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::{Outcome, State};
+use sodiumoxide::utils::memcmp;
+
+// Token that requests to the node's wallet and miner have to carry, if any
+pub struct ApiAuth(pub Option<String>);
+
+// Request guard of the endpoints that spend the node's tulips or use its wallet.
+// Requests carry the token as `Authorization: Bearer <token>`.
+pub struct Authorized;
+
+impl<'a, 'r> FromRequest<'a, 'r> for Authorized {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Authorized, ()> {
+        let auth = match request.guard::<State<ApiAuth>>() {
+            Outcome::Success(auth) => auth,
+            _ => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+
+        let token = match auth.0 {
+            Some(ref token) => token,
+            None => return Outcome::Success(Authorized),
+        };
+
+        let expected = format!("Bearer {}", token);
+        match request.headers().get_one("Authorization") {
+            Some(given) if memcmp(given.as_bytes(), expected.as_bytes()) => {
+                Outcome::Success(Authorized)
+            }
+            _ => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
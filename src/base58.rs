@@ -0,0 +1,61 @@
+// Base58 encoding, with the alphabet Bitcoin uses. It leaves out characters that look
+// alike, like 0 and O, so that encoded strings can be copied by hand.
+
+const ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+pub fn encode(bytes: &[u8]) -> String {
+    // Digits of the number in base 58, least significant first
+    let mut digits: Vec<u8> = vec![];
+    for &byte in bytes {
+        let mut carry = byte as usize;
+        for digit in digits.iter_mut() {
+            carry += (*digit as usize) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+
+    // Leading zero bytes don't change the number, so each is written as a zero digit
+    let zeros = bytes.iter().take_while(|&&byte| byte == 0).count();
+
+    let mut encoded = String::with_capacity(zeros + digits.len());
+    for _ in 0..zeros {
+        encoded.push(ALPHABET[0] as char);
+    }
+    for &digit in digits.iter().rev() {
+        encoded.push(ALPHABET[digit as usize] as char);
+    }
+
+    encoded
+}
+
+// Returns None if the string holds a character outside of the alphabet
+pub fn decode(encoded: &str) -> Option<Vec<u8>> {
+    // Bytes of the number, least significant first
+    let mut bytes: Vec<u8> = vec![];
+    for c in encoded.bytes() {
+        let mut carry = ALPHABET.iter().position(|&digit| digit == c)?;
+        for byte in bytes.iter_mut() {
+            carry += (*byte as usize) * 58;
+            *byte = (carry & 0xff) as u8;
+            carry >>= 8;
+        }
+
+        while carry > 0 {
+            bytes.push((carry & 0xff) as u8);
+            carry >>= 8;
+        }
+    }
+
+    let zeros = encoded.bytes().take_while(|&c| c == ALPHABET[0]).count();
+
+    let mut decoded = vec![0; zeros];
+    decoded.extend(bytes.iter().rev());
+
+    Some(decoded)
+}
@@ -0,0 +1,351 @@
+// Command line client for a tulipchain node. Transactions are signed here with the
+// keys of a local wallet file, so only signed transactions are sent to the node.
+
+extern crate argparse;
+extern crate reqwest;
+extern crate serde;
+extern crate serde_json;
+extern crate tulipchain;
+
+use argparse::{ArgumentParser, List, Store, StoreOption};
+use reqwest::header::{Authorization, Bearer};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+use std::path::PathBuf;
+use std::process::exit;
+use tulipchain::address::{self, Address, AddressError};
+use tulipchain::wallet::{Wallet, WalletError};
+use tulipchain::Tulips;
+
+// Exit code for a command that doesn't make sense, following sysexits.h
+const EX_USAGE: i32 = 64;
+
+// Entries asked for per request when listing an address's history, which is the
+// most the node serves at once
+const HISTORY_PAGE_LIMIT: usize = 100;
+
+#[derive(Debug)]
+enum CliError {
+    // The command or its arguments don't make sense
+    Usage(String),
+    Address(AddressError),
+    Wallet(WalletError),
+    // The node couldn't be reached, or answered with something unexpected
+    Request(String),
+    // The node answered with an error status, and the reason it gave if any
+    Rejected(String, reqwest::StatusCode, Option<String>),
+    // None of the wallet's addresses can afford the payment on its own
+    Funds(Tulips),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CliError::Usage(ref message) => write!(f, "{}", message),
+            CliError::Address(ref err) => write!(f, "{}", err),
+            CliError::Wallet(ref err) => write!(f, "wallet error: {}", err),
+            CliError::Request(ref url) => write!(f, "request to {} failed", url),
+            CliError::Rejected(ref url, status, None) => write!(f, "{} answered {}", url, status),
+            CliError::Rejected(ref url, status, Some(ref message)) => {
+                write!(f, "{} answered {}: {}", url, status, message)
+            }
+            CliError::Funds(value) => write!(f, "no address holds {} available tulips", value),
+        }
+    }
+}
+
+// The api of the node the client talks to
+struct Node {
+    url: String,
+    client: reqwest::Client,
+
+    // Token the node's wallet and miner endpoints ask for, if any
+    token: Option<String>,
+}
+
+impl Node {
+    fn read<T: DeserializeOwned>(
+        url: &str,
+        response: reqwest::Result<reqwest::Response>,
+    ) -> Result<T, CliError> {
+        let mut response = response.map_err(|_| CliError::Request(url.to_string()))?;
+        if !response.status().is_success() {
+            // Transactions and blocks that are turned down come with a message
+            let message = response
+                .json::<Value>()
+                .ok()
+                .and_then(|body| body["message"].as_str().map(String::from));
+
+            return Err(CliError::Rejected(
+                url.to_string(),
+                response.status(),
+                message,
+            ));
+        }
+
+        response
+            .json()
+            .map_err(|_| CliError::Request(url.to_string()))
+    }
+
+    fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, CliError> {
+        let url = format!("{}{}", self.url, path);
+        let mut request = self.client.get(&url);
+        self.authorize(&mut request);
+
+        Node::read(&url, request.send())
+    }
+
+    fn post<B: Serialize, T: DeserializeOwned>(&self, path: &str, body: &B) -> Result<T, CliError> {
+        let url = format!("{}{}", self.url, path);
+        let mut request = self.client.post(&url);
+        self.authorize(request.json(body));
+
+        Node::read(&url, request.send())
+    }
+
+    fn authorize(&self, request: &mut reqwest::RequestBuilder) {
+        if let Some(ref token) = self.token {
+            request.header(Authorization(Bearer {
+                token: token.clone(),
+            }));
+        }
+    }
+
+    fn balance(&self, address: &Address) -> Result<Value, CliError> {
+        self.get(&format!("/addresses/{}/balance", address))
+    }
+}
+
+fn tulips(value: &Value, field: &str) -> Tulips {
+    value[field].as_u64().unwrap_or(0) as Tulips
+}
+
+fn balance(node: &Node, wallet: &Wallet) -> Result<(), CliError> {
+    let mut total: Tulips = 0;
+    for address in wallet.addresses() {
+        let balance = node.balance(&address)?;
+        println!(
+            "{}  confirmed {}  pending {}  available {}",
+            address,
+            tulips(&balance, "confirmed"),
+            tulips(&balance, "pending"),
+            tulips(&balance, "available")
+        );
+        total = total.saturating_add(tulips(&balance, "available"));
+    }
+
+    println!("Available in total: {}", total);
+
+    Ok(())
+}
+
+// Pays from the first address that can afford the payment, signing the transaction
+// with its key
+fn send(node: &Node, wallet: &Wallet, args: &[String]) -> Result<(), CliError> {
+    if args.len() != 2 {
+        return Err(CliError::Usage(String::from(
+            "usage: send <address> <amount>",
+        )));
+    }
+
+    let recipient_addr = address::decode(&args[0]).map_err(CliError::Address)?;
+    let value: Tulips = args[1]
+        .parse()
+        .map_err(|_| CliError::Usage(format!("'{}' isn't an amount of tulips", args[1])))?;
+
+    for address in wallet.addresses() {
+        let balance = node.balance(&address)?;
+        if tulips(&balance, "available") < value {
+            continue;
+        }
+
+        // The node turns down a sequence number that's been used before
+        let sequence = balance["next_sequence"].as_u64().unwrap_or(0);
+        let transaction = address.new_transaction(value, recipient_addr, sequence);
+        let submitted: Value = node.post("/transactions/submit", &transaction)?;
+        println!("Sent {} tulips from {}", value, address);
+        println!("Transaction {}", submitted["txid"].as_str().unwrap_or(""));
+
+        return Ok(());
+    }
+
+    Err(CliError::Funds(value))
+}
+
+// Downloads an address's whole history, a page at a time
+fn address_history(node: &Node, address: &Address) -> Result<Vec<Value>, CliError> {
+    let mut entries: Vec<Value> = vec![];
+
+    loop {
+        let page: Vec<Value> = node.get(&format!(
+            "/addresses/{}/transactions?from={}&limit={}",
+            address,
+            entries.len(),
+            HISTORY_PAGE_LIMIT
+        ))?;
+
+        let done = page.len() < HISTORY_PAGE_LIMIT;
+        entries.extend(page);
+        if done {
+            return Ok(entries);
+        }
+    }
+}
+
+fn history(node: &Node, wallet: &Wallet) -> Result<(), CliError> {
+    for address in wallet.addresses() {
+        let entries = address_history(node, &address)?;
+        println!("{}", address);
+
+        let own_addr = Value::String(address.to_string());
+        for entry in entries {
+            let height = match entry["height"].as_u64() {
+                Some(height) => height.to_string(),
+                None => String::from("pending"),
+            };
+
+            let transaction = &entry["transaction"];
+            let (change, counterparty) = if transaction["recipient_addr"] == own_addr {
+                ("+", &transaction["sender_addr"])
+            } else {
+                ("-", &transaction["recipient_addr"])
+            };
+
+            println!(
+                "  {:>8}  {}  {}{}  {}",
+                height,
+                entry["txid"].as_str().unwrap_or(""),
+                change,
+                tulips(transaction, "value"),
+                counterparty.as_str().unwrap_or("mining reward")
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn new_address(wallet: &mut Wallet) -> Result<(), CliError> {
+    let address = wallet.new_address();
+    wallet.persist().map_err(CliError::Wallet)?;
+    println!("{}", address);
+
+    Ok(())
+}
+
+fn mine(node: &Node) -> Result<(), CliError> {
+    let header: Value = node.get("/mine")?;
+    println!("Mined block {}", header["ind"]);
+
+    Ok(())
+}
+
+fn peers(node: &Node) -> Result<(), CliError> {
+    let peers: Vec<String> = node.get("/network/peers")?;
+    for peer in peers {
+        println!("{}", peer);
+    }
+
+    Ok(())
+}
+
+fn show(value: &Value) {
+    println!("{}", serde_json::to_string_pretty(value).unwrap());
+}
+
+fn chain_info(node: &Node) -> Result<(), CliError> {
+    let status: Value = node.get("/")?;
+    show(&status);
+
+    Ok(())
+}
+
+fn tx(node: &Node, args: &[String]) -> Result<(), CliError> {
+    if args.len() != 1 {
+        return Err(CliError::Usage(String::from("usage: tx <txid>")));
+    }
+
+    let entry: Value = node.get(&format!("/transactions/{}", args[0]))?;
+    show(&entry);
+
+    Ok(())
+}
+
+// Opens the wallet file, creating it if it doesn't exist yet
+fn open_wallet(path: &str) -> Result<Wallet, CliError> {
+    let (wallet, created) = Wallet::open(PathBuf::from(path)).map_err(CliError::Wallet)?;
+    if created {
+        eprintln!(
+            "Created a new wallet. Write down its seed phrase to be able to recover it: {}",
+            wallet.phrase()
+        );
+    }
+
+    Ok(wallet)
+}
+
+// Runs the command. Only the commands that sign or look at the wallet's addresses
+// open the wallet, so the others don't create one.
+fn run(node: &Node, wallet_path: &str, command: &str, args: &[String]) -> Result<(), CliError> {
+    match command {
+        "balance" => balance(node, &open_wallet(wallet_path)?),
+        "send" => send(node, &open_wallet(wallet_path)?, args),
+        "history" => history(node, &open_wallet(wallet_path)?),
+        "new-address" => new_address(&mut open_wallet(wallet_path)?),
+        "mine" => mine(node),
+        "peers" => peers(node),
+        "chain-info" => chain_info(node),
+        "tx" => tx(node, args),
+        _ => Err(CliError::Usage(format!("unknown command '{}'", command))),
+    }
+}
+
+fn main() {
+    let mut url = String::from("http://localhost:8000");
+    let mut wallet_path = String::from("wallet.json");
+    let mut token: Option<String> = None;
+    let mut command = String::new();
+    let mut args: Vec<String> = vec![];
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Tulipchain command line wallet");
+        ap.refer(&mut url)
+            .add_option(&["--node"], Store, "base url of the node to talk to");
+        ap.refer(&mut wallet_path).add_option(
+            &["--wallet"],
+            Store,
+            "wallet file to sign with, created if it doesn't exist",
+        );
+        ap.refer(&mut token).add_option(
+            &["--token"],
+            StoreOption,
+            "api token of the node, if it asks for one",
+        );
+        ap.refer(&mut command).required().add_argument(
+            "command",
+            Store,
+            "balance, send, history, new-address, mine, peers, chain-info or tx",
+        );
+        ap.refer(&mut args)
+            .add_argument("arguments", List, "arguments of the command");
+        ap.stop_on_first_argument(true);
+        ap.parse_args_or_exit();
+    }
+
+    let node = Node {
+        url: url.trim_right_matches('/').to_string(),
+        client: reqwest::Client::new(),
+        token,
+    };
+
+    if let Err(err) = run(&node, &wallet_path, &command, &args) {
+        eprintln!("{}", err);
+        exit(match err {
+            CliError::Usage(_) => EX_USAGE,
+            _ => 1,
+        });
+    }
+}
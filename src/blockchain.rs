@@ -1,34 +1,174 @@
-use super::transaction::Transaction;
-use super::address::Address;
+use super::transaction::{Transaction, TxError};
+use super::address::{self, Address};
+use super::clock::SharedClock;
+use super::hex;
+use super::index::{AddressIndex, Balance, HistoryEntry};
+use super::merkle::{self, MerkleProof, ProvenTransaction};
+use super::metrics::ChainMetrics;
+use super::keys::{KeySource, OsKeys};
+use super::miner::MiningState;
+use super::bootstrap::{self, BootstrapError, BootstrapOptions};
+use super::spec::ChainSpec;
+use super::store::{self, ChainStore};
+use super::wire::{self, Message};
 use super::{Digest, PublicKey, ServerConfig, Tulips};
 
 use reqwest::header::{ContentType, Headers};
 use sodiumoxide::crypto::hash;
 use std::collections::{HashMap, HashSet};
-use bincode::serialize;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Instant;
+use bincode::{deserialize, serialize};
 use chrono::prelude::*;
-use std::process::exit;
-use std::sync::RwLock;
+use log::Level;
+use std::fmt;
+use std::mem;
 use serde_json;
 use reqwest;
 
-type Nonce = u32;
-type NodeAddr = String;
+pub type Nonce = u32;
+pub type NodeAddr = String;
 
-const GENESIS_PREV_NONCE: u32 = 0;
+// The chain as shared between the http handlers and the peer protocol
+pub type SharedChain = Arc<RwLock<Blockchain>>;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Block {
-    ind: usize,
-    timestamp: DateTime<Utc>,
-    pub transactions: Vec<Transaction>,
+// Version of the block encoding. It's bumped whenever the serialized form of a block
+// changes, so that blocks in a format a node doesn't understand are rejected.
+pub const BLOCK_VERSION: u32 = 5;
+
+// The parts of a block needed to check the proof of work and how blocks link up. The
+// header commits to the body through the merkle root, so its hash identifies the
+// whole block.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BlockHeader {
+    // Encoding version, which always comes first so that it can be read on its own
+    pub version: u32,
+    pub ind: usize,
+    pub timestamp: DateTime<Utc>,
+    #[serde(with = "hex::option")]
     pub previous_hash: Option<Digest>,
 
+    // Root of the merkle tree over the ids of the coinbase and the other transactions
+    #[serde(with = "hex")]
+    pub merkle_root: Digest,
+    pub nonce: Nonce,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BlockBody {
     // The coinbase hash can be verified
     pub coinbase_transaction: Transaction,
+    pub transactions: Vec<Transaction>,
+}
 
-    #[serde(skip)]
-    pub nonce: Nonce,
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub body: BlockBody,
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    // The bytes don't hold a block
+    Malformed,
+    // The block is encoded in a version this node doesn't understand
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::Malformed => write!(f, "malformed block"),
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported block version {}", version)
+            }
+        }
+    }
+}
+
+// Why a block, or a chain of them, is turned down. Blocks are referred to by height.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockError {
+    // The block is encoded in a version this node doesn't understand
+    UnsupportedVersion { height: usize, version: u32 },
+    // The block doesn't follow the one it builds on, which for a block received from
+    // a peer usually means that we're missing blocks
+    BadHeight { height: usize, expected: usize },
+    // The block doesn't link to the hash of the one it builds on
+    BadPrevHash { height: usize },
+    // The header's hash doesn't meet the difficulty
+    BadPow { height: usize },
+    // The header doesn't commit to the block's transactions
+    BadMerkleRoot { height: usize },
+    // One of the block's transactions is invalid, or the senders can't afford them
+    BadTransaction { height: usize, error: TxError },
+    // The coinbase doesn't pay the block reward, or the block has more than one
+    BadCoinbase { height: usize },
+}
+
+impl BlockError {
+    // Short name of the error, as used in metrics and error responses
+    pub fn reason(&self) -> &'static str {
+        match *self {
+            BlockError::UnsupportedVersion { .. } => "unsupported_version",
+            BlockError::BadHeight { .. } | BlockError::BadPrevHash { .. } => "not_on_tip",
+            BlockError::BadPow { .. } => "invalid_pow",
+            BlockError::BadMerkleRoot { .. } => "invalid_merkle_root",
+            BlockError::BadTransaction { .. } => "invalid_transaction",
+            BlockError::BadCoinbase { .. } => "invalid_coinbase",
+        }
+    }
+
+    // Whether the block may well be valid, but builds on blocks we don't have. That's
+    // the case for a block past our tip, and for one that competes with our tip's
+    // successor, since headers are checked for their height before they're checked
+    // for the hash they link to.
+    pub fn is_ahead(&self) -> bool {
+        match *self {
+            BlockError::BadHeight { height, expected } => height > expected,
+            BlockError::BadPrevHash { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BlockError::UnsupportedVersion { height, version } => write!(
+                f,
+                "block at height {} has unsupported version {}",
+                height, version
+            ),
+            BlockError::BadHeight { height, expected } => write!(
+                f,
+                "block at height {} doesn't follow the tip, expected height {}",
+                height, expected
+            ),
+            BlockError::BadPrevHash { height } => write!(
+                f,
+                "block at height {} doesn't link to the block before it",
+                height
+            ),
+            BlockError::BadPow { height } => {
+                write!(f, "block at height {} doesn't meet the difficulty", height)
+            }
+            BlockError::BadMerkleRoot { height } => {
+                write!(f, "block at height {} has an invalid merkle root", height)
+            }
+            BlockError::BadTransaction { height, ref error } => {
+                write!(
+                    f,
+                    "block at height {} holds an invalid transaction: {}",
+                    height, error
+                )
+            }
+            BlockError::BadCoinbase { height } => {
+                write!(f, "block at height {} has an invalid coinbase", height)
+            }
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -43,308 +183,963 @@ pub struct Blockchain {
     #[serde(skip)]
     pub address: Option<Address>,
 
-    // Each node maintains a dictionary holding balances of all other nodes
-    // In a persistent implementation, this might involve a database
-    #[serde(skip)]
-    pub address_balances: HashMap<PublicKey, Tulips>,
-
     // Peers gossip to maintain synchronization
     pub peers: HashSet<NodeAddr>,
+
+    // Addresses that peers accept binary protocol connections on, keyed by their
+    // http address. Peers without one are reached over http instead.
+    #[serde(skip)]
+    pub wire_peers: HashMap<NodeAddr, String>,
+
+    // Port this node accepts binary protocol connections on, if any
+    #[serde(skip)]
+    pub p2p_port: Option<u16>,
+
+    // Where the chain is persisted, if anywhere
+    #[serde(skip)]
+    pub store: Option<ChainStore>,
+
+    // The network this chain belongs to
+    #[serde(skip)]
+    pub spec: ChainSpec,
+
+    // Transactions and balances of the addresses in the chain
+    #[serde(skip)]
+    pub index: AddressIndex,
+
+    // Where mining rewards are paid to, if not to the node's address
+    #[serde(skip)]
+    pub reward_addr: Option<PublicKey>,
+
+    #[serde(skip)]
+    pub mining: MiningState,
+
+    #[serde(skip)]
+    pub metrics: ChainMetrics,
+
+    // Where the timestamps of mined blocks come from
+    #[serde(skip)]
+    pub clock: SharedClock,
 }
 
-impl Block {
-    // Returns an owned digest of the block
-    fn hash(&self) -> Digest {
-        let serialized: Vec<u8> = serialize(self).unwrap();
+impl BlockHeader {
+    // Returns an owned digest of the header. It's what the proof of work is done on,
+    // and what the next block links to.
+    pub fn hash(&self) -> Digest {
+        let serialized = serialize(self).unwrap();
         let hash::sha256::Digest(ref digest) = hash::sha256::hash(&serialized);
 
         digest.to_vec()
     }
 
-    // Check that all the transactions inside a block are valid
-    pub fn validate_transactions(&self) -> bool {
-        for transaction in &self.transactions {
-            if !transaction.verify_digest() {
-                return false;
+    // Checks the proof of work: the difficulty is the number of leading bytes of the
+    // hash that have to be zero. Requiring more than 2 results in a very large time to
+    // find the nonce for a toy implementation.
+    pub fn meets_difficulty(&self, difficulty: usize) -> bool {
+        self.hash()[0..difficulty].iter().all(|&byte| byte == 0)
+    }
+}
+
+impl BlockBody {
+    // Ids of the transactions in the order they're placed in the merkle tree, starting
+    // with the coinbase
+    pub fn txids(&self) -> Vec<Digest> {
+        let mut txids = vec![self.coinbase_transaction.id()];
+        txids.extend(self.transactions.iter().map(|transaction| transaction.id()));
+
+        txids
+    }
+
+    pub fn merkle_root(&self) -> Digest {
+        merkle::root(&self.txids())
+    }
+
+    // The transactions in the order they're placed in the merkle tree
+    pub fn all_transactions(&self) -> Vec<&Transaction> {
+        let mut transactions = vec![&self.coinbase_transaction];
+        transactions.extend(self.transactions.iter());
+
+        transactions
+    }
+
+    pub fn transaction_at(&self, position: usize) -> Option<&Transaction> {
+        if position == 0 {
+            Some(&self.coinbase_transaction)
+        } else {
+            self.transactions.get(position - 1)
+        }
+    }
+}
+
+impl Block {
+    // Assembles a block, committing to the transactions in its header
+    pub fn new(
+        ind: usize,
+        timestamp: DateTime<Utc>,
+        transactions: Vec<Transaction>,
+        previous_hash: Option<Digest>,
+        coinbase_transaction: Transaction,
+        nonce: Nonce,
+    ) -> Block {
+        let body = BlockBody {
+            coinbase_transaction,
+            transactions,
+        };
+
+        Block {
+            header: BlockHeader {
+                version: BLOCK_VERSION,
+                ind,
+                timestamp,
+                previous_hash,
+                merkle_root: body.merkle_root(),
+                nonce,
+            },
+            body,
+        }
+    }
+
+    // Encodes the block in its binary form: the bincode encoding of the header's
+    // fields followed by the body's, in order, starting with the version
+    pub fn encode(&self) -> Vec<u8> {
+        serialize(self).unwrap()
+    }
+
+    // Decodes a block, checking its version before the rest of it is read
+    pub fn decode(bytes: &[u8]) -> Result<Block, DecodeError> {
+        let version: u32 = deserialize(bytes).map_err(|_| DecodeError::Malformed)?;
+        if version != BLOCK_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+
+        deserialize(bytes).map_err(|_| DecodeError::Malformed)
+    }
+
+    // The block is identified by the hash of its header
+    pub fn hash(&self) -> Digest {
+        self.header.hash()
+    }
+
+    // Checks that the header commits to the block's transactions
+    pub fn has_valid_merkle_root(&self) -> bool {
+        self.body.merkle_root() == self.header.merkle_root
+    }
+
+    // Builds a proof that the transaction with the given id is part of the block
+    pub fn prove_transaction(&self, txid: &[u8]) -> Option<MerkleProof> {
+        let txids = self.body.txids();
+        let index = txids.iter().position(|id| &id[..] == txid)?;
+
+        merkle::prove(&txids, index).map(|path| MerkleProof {
+            txid: txid.to_vec(),
+            height: self.header.ind,
+            block_hash: self.hash(),
+            merkle_root: self.header.merkle_root.clone(),
+            path,
+        })
+    }
+
+    // Checks that the block pays out the reward, and nothing else, through a coinbase
+    // that carries its height
+    pub fn validate_coinbase(&self, reward: Tulips) -> Result<(), BlockError> {
+        let coinbase = &self.body.coinbase_transaction;
+        if coinbase.sender_addr.is_some()
+            || coinbase.value != reward
+            || coinbase.sequence != self.header.ind as u64
+        {
+            return Err(BlockError::BadCoinbase {
+                height: self.header.ind,
+            });
+        }
+
+        Ok(())
+    }
+
+    // Checks that all the transactions inside a block are signed by their senders. The
+    // genesis block's allocations are paid out of nothing, like coinbase transactions,
+    // but any other block has only one transaction without a sender.
+    pub fn validate_transactions(&self) -> Result<(), BlockError> {
+        if self.header.ind == 0 {
+            return Ok(());
+        }
+
+        for transaction in &self.body.transactions {
+            if transaction.sender_addr.is_none() {
+                return Err(BlockError::BadCoinbase {
+                    height: self.header.ind,
+                });
             }
+
+            transaction
+                .verify_digest()
+                .map_err(|error| BlockError::BadTransaction {
+                    height: self.header.ind,
+                    error,
+                })?;
+        }
+
+        Ok(())
+    }
+
+    // Checks that the header commits to the body, that the coinbase pays the given
+    // reward, and that the transactions are valid. Whether the senders can afford them
+    // depends on the chain, and is checked when the block is added to the index.
+    pub fn validate_body(&self, reward: Tulips) -> Result<(), BlockError> {
+        if !self.has_valid_merkle_root() {
+            return Err(BlockError::BadMerkleRoot {
+                height: self.header.ind,
+            });
         }
 
-        true
+        self.validate_coinbase(reward)?;
+        self.validate_transactions()
     }
 }
 
 impl Blockchain {
-    // Creates a new blockchain, including a genesis block.
-    // The genesis block (including nonce) will be identical for all new chains.
+    // Creates a new blockchain on the development network, including a genesis block
     pub fn new() -> Blockchain {
-        let mut blockchain = Blockchain {
-            pending_transactions: vec![],
-            chain: vec![],
+        Blockchain::with_spec(ChainSpec::dev())
+    }
 
-            address: Some(Address::new()),
-            address_balances: HashMap::new(),
-            peers: HashSet::new(),
-        };
+    // Creates a new blockchain, including a genesis block.
+    // The genesis block (including nonce) will be identical for all chains of a network.
+    pub fn with_spec(spec: ChainSpec) -> Blockchain {
+        Blockchain::with_sources(spec, SharedClock::default(), &mut OsKeys)
+    }
+
+    // Creates a new blockchain whose blocks are timestamped by the given clock, and
+    // whose address is the next one of the key source. Chains created from the same
+    // sources mine identical blocks.
+    pub fn with_sources<K: KeySource + ?Sized>(
+        spec: ChainSpec,
+        clock: SharedClock,
+        keys: &mut K,
+    ) -> Blockchain {
+        let mut blockchain = Blockchain::from_blocks(spec, vec![]);
+        blockchain.clock = clock;
+        blockchain.address = Some(Address::generate(keys));
 
         // Create the genesis block and start the chain
         let genesis_block = blockchain.create_genesis_block();
+        blockchain
+            .index
+            .add_block(&genesis_block)
+            .expect("the genesis block has no senders, so it always adds up");
         blockchain.chain.push(genesis_block);
 
         blockchain
     }
 
+    // Creates a blockchain from existing blocks, e.g. ones loaded from disk. It has no
+    // address until the caller gives it one.
+    pub fn from_blocks(spec: ChainSpec, chain: Vec<Block>) -> Blockchain {
+        Blockchain {
+            pending_transactions: vec![],
+            // Blocks that don't add up leave the index empty, and are turned down by
+            // validate_chain
+            index: AddressIndex::build(&chain).unwrap_or_default(),
+            chain,
+
+            address: None,
+            peers: HashSet::new(),
+            wire_peers: HashMap::new(),
+            p2p_port: None,
+            store: None,
+            spec,
+            reward_addr: None,
+            mining: MiningState::OnDemand,
+            metrics: ChainMetrics::default(),
+            clock: SharedClock::default(),
+        }
+    }
+
+    // Creates the genesis block described by the chain spec
     pub fn create_genesis_block(&self) -> Block {
+        let genesis = &self.spec.genesis;
         let coinbase_transaction =
-            Transaction::create_coinbase_transaction(self.address.clone().unwrap().public_key);
-
-        let mut genesis_block = Block {
-            ind: 0,
-            timestamp: Utc::now(),
-            transactions: vec![],
-            previous_hash: None,
-            nonce: 0,
-            coinbase_transaction,
-        };
+            Transaction::create_coinbase_transaction(genesis.coinbase_addr, self.spec.reward, 0);
+
+        // Premined tulips are handed out like coinbase transactions
+        let transactions = genesis
+            .premine
+            .iter()
+            .map(|allocation| Transaction::new(None, allocation.addr, allocation.value, 0))
+            .collect();
 
-        // The previous nonce of the genesis block is set by convention
-        genesis_block.nonce =
-            Blockchain::find_nonce(GENESIS_PREV_NONCE, &genesis_block.previous_hash);
+        Block::new(
+            0,
+            genesis.timestamp,
+            transactions,
+            None,
+            coinbase_transaction,
+            genesis.nonce,
+        )
+    }
 
-        genesis_block
+    pub fn genesis_hash(&self) -> Digest {
+        self.create_genesis_block().hash()
     }
 
-    pub fn init_chain(base_addr: String, config: &ServerConfig) -> RwLock<Blockchain> {
-        if base_addr.is_empty() {
-            println!("No input node provided, creating new blockchain instance");
-            return RwLock::new(Blockchain::new());
+    // Checks that a chain loaded from elsewhere starts at our genesis block
+    fn has_genesis(&self, blocks: &[Block]) -> bool {
+        match blocks.first() {
+            Some(genesis) => genesis.hash() == self.genesis_hash(),
+            None => false,
         }
+    }
+
+    // Creates the node's chain. A persisted chain is resumed if there is one, and then
+    // synchronized with the network through the seed nodes. Without any seeds, a new
+    // chain is started instead.
+    pub fn init_chain(
+        options: BootstrapOptions,
+        config: &ServerConfig,
+    ) -> Result<Blockchain, BootstrapError> {
+        let BootstrapOptions {
+            seeds,
+            attempts,
+            snapshot,
+            store,
+            spec,
+        } = options;
 
-        // Try to get blockchain from the source node using a http request
-        let join_url = format!("{}/join", base_addr);
+        info!("Starting node on the {} network", spec.network);
+        let mut chain = Blockchain::with_spec(spec);
 
-        // Create a client and send a request to to join
-        let client = reqwest::Client::new();
-        let serialized_config = serde_json::to_string(config).unwrap();
+        let persisted = match store {
+            Some(ref store) => store.load().map_err(BootstrapError::Store)?,
+            None => None,
+        };
 
-        let mut headers = Headers::new();
-        headers.set(ContentType::json());
-        let request_result = client
-            .post(&join_url)
-            .headers(headers)
-            .body(serialized_config)
-            .send();
+        if let Some(blocks) = persisted {
+            if !chain.has_genesis(&blocks) {
+                return Err(BootstrapError::GenesisMismatch);
+            }
 
-        if let Err(_) = request_result {
-            println!("An error occured while making a request to the source node");
-            exit(1);
+            info!("Resuming persisted chain of {} blocks", blocks.len());
+            chain
+                .reorganize(0, blocks)
+                .map_err(BootstrapError::InvalidStore)?;
         }
 
-        // Look into the text of the request result
-        let request_text_result = request_result.unwrap().text();
-        if let Err(_) = request_text_result {
-            println!("An error occured while reading the response from the source node");
-            exit(1);
+        chain.store = store;
+        chain.persist();
+
+        if seeds.is_empty() {
+            info!("No input node provided, not joining a network");
+            return Ok(chain);
         }
 
-        // Text holds the serialized blockchain
-        let text = request_text_result.unwrap();
+        let err = match bootstrap::join_network(&mut chain, &seeds, attempts, config) {
+            Ok(()) => return Ok(chain),
+            Err(err) => err,
+        };
+
+        // Fall back to the snapshot, if one was provided
+        let path = match snapshot {
+            Some(path) => path,
+            None => return Err(err),
+        };
 
-        let deserialized: Result<Blockchain, _> = serde_json::from_str(&text);
-        if let Err(_) = deserialized {
-            println!("An error occured while deserializing the blockchain");
-            exit(1);
+        warn!(
+            "Couldn't join the network ({}), starting from snapshot {}",
+            err,
+            path.display()
+        );
+
+        let blocks = store::read_snapshot(&path).map_err(BootstrapError::Snapshot)?;
+        if !chain.has_genesis(&blocks) {
+            return Err(BootstrapError::GenesisMismatch);
         }
 
-        let mut chain = deserialized.unwrap();
-        chain.register_peer(base_addr);
+        Blockchain::from_blocks(chain.spec.clone(), blocks.clone())
+            .validate_chain()
+            .map_err(BootstrapError::InvalidSnapshot)?;
+
+        // Keep what was persisted if it's further along than the snapshot
+        if blocks.len() > chain.chain.len() {
+            chain
+                .reorganize(0, blocks)
+                .map_err(BootstrapError::InvalidSnapshot)?;
+        }
 
-        RwLock::new(chain)
+        Ok(chain)
     }
 
     // Registers a new mining peer
     pub fn register_peer(&mut self, addr: NodeAddr) {
-        println!("Registering peer with address {}", addr);
+        if !self.peers.contains(&addr) {
+            log_event!(Level::Info, "Peer joined", peer = addr);
+        }
 
         self.peers.insert(addr);
     }
 
-    // Verifies the transaction signature and adds it to the
-    // list of pending transactions
-    pub fn append_transaction(&mut self, transaction: Transaction) -> bool {
-        // Check that the transaction is valid
-        if !transaction.verify_digest() {
-            return false;
-        }
-
-        if let None = transaction.sender_addr {
-            // The transaction is a coinbase, so we look through blocks to find it
-            let mut found = false;
-            for block in self.chain.iter().rev() {
-                if transaction == block.coinbase_transaction {
-                    found = true;
-                }
-            }
+    // Records the address a peer accepts binary protocol connections on
+    pub fn register_wire_peer(&mut self, addr: NodeAddr, wire_addr: String) {
+        debug!("Peer {} speaks the binary protocol on {}", addr, wire_addr);
 
-            // If it wasn't the coinbase for any block already in the chain,
-            // then it must be counterfeit (coinbase transactions aren't added using
-            // append_transaction)
-            if !found {
-                return false;
-            }
-        } else {
-            // Otherwise, just verify that it was signed by the sender
-            if !transaction.verify_digest() {
-                return false;
-            }
+        self.wire_peers.insert(addr, wire_addr);
+    }
+
+    // Verifies the transaction signature and the sender's balance, and adds it to the
+    // list of pending transactions
+    pub fn append_transaction(&mut self, transaction: Transaction) -> Result<(), TxError> {
+        if let Err(err) = self.check_transaction(&transaction) {
+            self.reject_transaction(&transaction, &err);
+            return Err(err);
         }
 
+        self.metrics.transactions_accepted += 1;
+        log_event!(
+            Level::Debug,
+            "Accepted transaction",
+            txid = hex::encode(&transaction.id()),
+            value = transaction.value,
+        );
+
+        self.pending_transactions.push(transaction);
+
+        Ok(())
+    }
+
+    fn check_transaction(&self, transaction: &Transaction) -> Result<(), TxError> {
+        // Coinbase transactions are only ever part of the block paying them out, and
+        // don't verify on their own since they have no sender
+        transaction.verify_digest()?;
+
+        // A transaction that's already been confirmed or is pending, or a different one
+        // with the same sequence number, is turned down, so that it can't be replayed
         let sender_addr = transaction.sender_addr.unwrap();
-        if self.address_balances.contains_key(&sender_addr) {
-            let balance = self.address_balances.get(&sender_addr).unwrap();
-            // Ensure the address has enough tulips
-            if *balance < transaction.value {
-                return false;
+        let balance = self.balance(&sender_addr);
+        if transaction.sequence != balance.next_sequence {
+            return Err(TxError::BadSequence {
+                expected: balance.next_sequence,
+                sequence: transaction.sequence,
+            });
+        }
+
+        // Ensure the sender has enough tulips, counting the payments it already made
+        let available = balance.available;
+        if available < transaction.value {
+            return Err(TxError::InsufficientFunds {
+                available,
+                value: transaction.value,
+            });
+        }
+
+        Ok(())
+    }
+
+    // Assembles the next block from the pending transactions, paying the reward to the
+    // given address. It still needs a proof of work.
+    pub fn next_block(&self, reward_addr: PublicKey) -> Block {
+        let coinbase_transaction = Transaction::create_coinbase_transaction(
+            reward_addr,
+            self.spec.reward,
+            self.chain.len(),
+        );
+
+        // There is always an earlier block, the genesis block is part of every chain
+        let previous_hash = self.chain.last().map(|block| block.hash());
+
+        Block::new(
+            self.chain.len(),
+            self.clock.now(),
+            self.pending_transactions.clone(),
+            previous_hash,
+            coinbase_transaction,
+            0,
+        )
+    }
+
+    // Appends a block holding the pending transactions to the chain
+    pub fn append_block(&mut self, block: Block) {
+        // Empty the list of pending transactions
+        self.pending_transactions = vec![];
+
+        // Push the confirmed transactions onto the chain. Pending transactions were
+        // checked against the ledger as they came in, and again whenever it changed.
+        self.metrics.blocks_accepted += 1;
+        self.index
+            .add_block(&block)
+            .expect("pending transactions always add up");
+        self.chain.push(block);
+        self.persist_tip();
+    }
+
+    // Adds a block announced by a peer, if it extends our tip. A block that's further
+    // ahead is turned down as well, which means that we're missing blocks.
+    pub fn accept_block(&mut self, block: Block) -> Result<(), BlockError> {
+        // The index is left as it was if the block doesn't add up
+        let checked = self
+            .check_block(&block)
+            .and_then(|()| self.index.add_block(&block));
+        if let Err(err) = checked {
+            self.reject_block(&block, &err);
+            return Err(err);
+        }
+
+        self.metrics.blocks_accepted += 1;
+        log_event!(
+            Level::Info,
+            "Accepted block",
+            height = block.header.ind,
+            hash = hex::encode(&block.hash()),
+            transactions = block.body.transactions.len(),
+        );
+
+        self.chain.push(block);
+        self.revalidate_pending();
+        self.persist_tip();
+
+        Ok(())
+    }
+
+    fn check_block(&self, block: &Block) -> Result<(), BlockError> {
+        // There is always an earlier block, the genesis block is part of every chain
+        let tip = self.chain.last().map(|tip| &tip.header);
+        Self::validate_header_chain(tip, &[block.header.clone()], self.spec.difficulty)?;
+
+        block.validate_body(self.spec.reward)
+    }
+
+    // Drops the pending transactions that no longer fit the ledger, e.g. because a
+    // block used up their sequence numbers or spent the tulips they'd pay with. That
+    // includes the ones that made it into a block.
+    fn revalidate_pending(&mut self) {
+        let pending = mem::replace(&mut self.pending_transactions, vec![]);
+        for transaction in pending {
+            if self.check_transaction(&transaction).is_ok() {
+                self.pending_transactions.push(transaction);
             }
-        } else {
-            // 0-balance addresses can't send more than 0 tulips
-            if transaction.value > 0 {
-                return false;
+        }
+    }
+
+    pub fn reward_addr(&self) -> PublicKey {
+        match self.reward_addr {
+            Some(reward_addr) => reward_addr,
+            None => self.address.as_ref().unwrap().public_key,
+        }
+    }
+
+    // Mines a block holding the pending transactions on top of the tip, paying the
+    // reward to the node's reward address
+    pub fn mine(&mut self) -> Block {
+        let reward_addr = self.reward_addr();
+        let mut block = self.next_block(reward_addr);
+
+        let started = Instant::now();
+        block.header.nonce = Blockchain::find_nonce(&block.header, self.spec.difficulty);
+        self.metrics
+            .searched(u64::from(block.header.nonce) + 1, started.elapsed());
+
+        log_event!(
+            Level::Info,
+            "Mined block",
+            height = block.header.ind,
+            hash = hex::encode(&block.hash()),
+        );
+        self.append_block(block.clone());
+
+        block
+    }
+
+    // Replaces every block from the fork height onwards with the given blocks, whose
+    // headers and bodies are expected to have been validated already. The chain is
+    // left as it is if the new blocks don't add up.
+    pub fn reorganize(&mut self, fork_height: usize, blocks: Vec<Block>) -> Result<(), BlockError> {
+        // Reorganizations are rare, so the index is simply rebuilt
+        let index = AddressIndex::build(self.chain.iter().take(fork_height).chain(&blocks))?;
+
+        let replaced = self.chain.len().saturating_sub(fork_height);
+
+        // Catching up replaces nothing, and resuming from the store or a snapshot only
+        // replaces the genesis block
+        if replaced > 0 && (fork_height > 0 || replaced > 1) {
+            self.metrics.reorganized(replaced);
+            if let Some(tip) = blocks.last() {
+                log_event!(
+                    Level::Info,
+                    "Reorganized chain",
+                    fork_height = fork_height,
+                    replaced = replaced,
+                    height = tip.header.ind,
+                    hash = hex::encode(&tip.hash()),
+                );
             }
+        }
+
+        // Blocks synchronized from peers, rather than resumed
+        if fork_height > 0 {
+            self.metrics.blocks_accepted += blocks.len() as u64;
+        }
+
+        self.chain.truncate(fork_height);
+        self.chain.extend(blocks);
+        self.index = index;
+        self.revalidate_pending();
+        self.persist();
 
-            self.address_balances.insert(sender_addr, 0);
+        Ok(())
+    }
+
+    // Writes the chain to the store, if the node has one
+    pub fn persist(&self) {
+        if let Some(ref store) = self.store {
+            if let Err(err) = store.save(&self.chain) {
+                error!("An error occured while persisting the chain: {}", err);
+            }
         }
+    }
 
-        // Set the recipient balance, if this is the first transaction it's been
-        // involved in
-        let recipient_addr = transaction.recipient_addr;
-        if !self.address_balances.contains_key(&recipient_addr) {
-            self.address_balances.insert(recipient_addr, 0);
+    // Adds the block that was just added to the chain to the store, if the node has one
+    fn persist_tip(&self) {
+        if let (Some(store), Some(tip)) = (self.store.as_ref(), self.chain.last()) {
+            if let Err(err) = store.append(tip) {
+                error!(
+                    "An error occured while persisting block {}: {}",
+                    tip.header.ind, err
+                );
+            }
         }
+    }
 
-        // Update the balances
-        self.address_balances
-            .get_mut(&sender_addr)
-            .map(|bal| *bal - transaction.value);
-        self.address_balances
-            .get_mut(&recipient_addr)
-            .map(|bal| *bal + transaction.value);
+    // Returns up to `count` headers, starting at the given height
+    pub fn headers(&self, from: usize, count: usize) -> Vec<BlockHeader> {
+        self.chain
+            .iter()
+            .skip(from)
+            .take(count)
+            .map(|block| block.header.clone())
+            .collect()
+    }
 
-        // Since it is, append it to the list of pending transaction
-        self.pending_transactions.push(transaction);
+    // Returns up to `count` blocks, starting at the given height
+    pub fn blocks(&self, from: usize, count: usize) -> Vec<Block> {
+        self.chain.iter().skip(from).take(count).cloned().collect()
+    }
 
-        true
+    // Finds the block holding the transaction, and proves that it's in there
+    pub fn transaction_proof(&self, txid: &[u8]) -> Option<MerkleProof> {
+        self.chain
+            .iter()
+            .rev()
+            .filter_map(|block| block.prove_transaction(txid))
+            .next()
     }
 
-    // Appends a new block to the chain, or starts the chain
-    pub fn append_block(
-        &mut self,
-        nonce: u32,
-        previous_hash: Option<Digest>,
-        reward_addr: PublicKey,
-    ) {
-        let coinbase_transaction = Transaction::create_coinbase_transaction(reward_addr);
-
-        let new_block = Block {
-            ind: self.chain.len() + 1,
-            timestamp: Utc::now(),
-            transactions: self.pending_transactions.clone(),
-            nonce,
-            coinbase_transaction,
-            previous_hash: {
-                if let Some(digest) = previous_hash {
-                    // If one was passed as an argument, use it instead of
-                    // looking at the chain
-                    Some(digest)
-                } else {
-                    if let Some(block) = self.chain.last() {
-                        // Compute the digest of the last block
-                        let digest = block.hash();
-
-                        Some(digest)
-                    } else {
-                        // There should always be a previous hash.
-                        // For the genesis block, one is chosen arbitrarily and passed
-                        // as an argument. Otherwise, there is always an earlier block.
-                        panic!("No previous hash!")
-                    }
-                }
-            },
-        };
+    // Looks a transaction up by its id, among the pending ones and then in the chain
+    pub fn find_transaction(&self, txid: &[u8]) -> Option<HistoryEntry> {
+        let pending = self
+            .pending_transactions
+            .iter()
+            .find(|transaction| &transaction.id()[..] == txid)
+            .map(|transaction| HistoryEntry {
+                txid: txid.to_vec(),
+                height: None,
+                transaction: transaction.clone(),
+            });
 
-        // Empty the list of pending transactions
-        self.pending_transactions = vec![];
+        pending.or_else(|| {
+            self.chain
+                .iter()
+                .rev()
+                .filter_map(|block| {
+                    block
+                        .body
+                        .all_transactions()
+                        .into_iter()
+                        .find(|transaction| &transaction.id()[..] == txid)
+                        .map(|transaction| HistoryEntry {
+                            txid: txid.to_vec(),
+                            height: Some(block.header.ind),
+                            transaction: transaction.clone(),
+                        })
+                })
+                .next()
+        })
+    }
 
-        // Push the confirmed transactions onto the chain
-        self.chain.push(new_block);
+    // Expected number of hashes it took to build the chain. Every block's proof of work
+    // takes 256 tries per leading zero byte.
+    pub fn cumulative_work(&self) -> u64 {
+        let block_work = 1u64
+            .checked_shl(8 * self.spec.difficulty as u32)
+            .unwrap_or(u64::max_value());
+
+        block_work.saturating_mul(self.chain.len() as u64)
     }
 
-    // Checks if a nonce is valid according to the mining condition
-    pub fn is_valid_nonce(last: Nonce, current: Nonce, prev_digest: &Option<Digest>) -> bool {
-        // Compute the digest
-        let serialized = serialize(&(last, current, prev_digest)).unwrap();
-        let hash::sha256::Digest(ref digest) = hash::sha256::hash(&serialized);
+    pub fn header_at(&self, height: usize) -> Option<BlockHeader> {
+        self.chain.get(height).map(|block| block.header.clone())
+    }
 
-        // Requiring more than the first 2 digits to be zeros resulting in very large
-        // time to find the nonce for a toy implementation.
-        &digest[0..2] == &[0, 0]
+    pub fn block_by_hash(&self, hash: &[u8]) -> Option<&Block> {
+        self.chain.iter().find(|block| &block.hash()[..] == hash)
     }
 
-    // Checks whether the chain is valid or not by check the nonce of each block
-    pub fn is_valid_chain(&self) -> bool {
-        for (i, block) in self.chain.iter().enumerate() {
-            // The genesis block is handled explicitly because of it's hardcoded
-            // previous nonce.
-            if i == 0 {
-                if !Self::is_valid_nonce(GENESIS_PREV_NONCE, block.nonce, &block.previous_hash) {
-                    return false;
+    // Checks whether an address appears in any transaction, either as sender or as
+    // recipient
+    pub fn is_address_used(&self, addr: &PublicKey) -> bool {
+        self.pending_transactions
+            .iter()
+            .any(|transaction| transaction.involves(addr))
+            || self.chain.iter().any(|block| {
+                block.body.coinbase_transaction.involves(addr)
+                    || block
+                        .body
+                        .transactions
+                        .iter()
+                        .any(|transaction| transaction.involves(addr))
+            })
+    }
+
+    // Proves every transaction in the chain the address is involved in, so that light
+    // nodes can check them against their headers
+    pub fn address_proofs(&self, addr: &PublicKey) -> Vec<ProvenTransaction> {
+        let mut proofs = vec![];
+
+        for block in &self.chain {
+            for transaction in block.body.all_transactions() {
+                if !transaction.involves(addr) {
+                    continue;
                 }
-            } else {
-                let previous_nonce = self.chain[i - 1].nonce;
-                if !Self::is_valid_nonce(previous_nonce, block.nonce, &block.previous_hash) {
-                    return false;
+
+                if let Some(proof) = block.prove_transaction(&transaction.id()) {
+                    proofs.push(ProvenTransaction {
+                        transaction: transaction.clone(),
+                        proof,
+                    });
                 }
             }
         }
 
-        true
+        proofs
+    }
+
+    // Balance of an address according to the ledger, without and with the pending
+    // transactions
+    pub fn balance(&self, addr: &PublicKey) -> Balance {
+        let confirmed = self.index.balance(addr);
+
+        let mut received: Tulips = 0;
+        let mut sent: Tulips = 0;
+        let mut next_sequence = self.index.next_sequence(addr);
+        for transaction in &self.pending_transactions {
+            if transaction.recipient_addr == *addr {
+                received = received.saturating_add(transaction.value);
+            }
+
+            if transaction.sender_addr.as_ref() == Some(addr) {
+                sent = sent.saturating_add(transaction.value);
+                next_sequence = next_sequence.max(transaction.sequence.saturating_add(1));
+            }
+        }
+
+        let available = confirmed.saturating_sub(sent);
+
+        Balance {
+            confirmed,
+            pending: available.saturating_add(received),
+            available,
+            next_sequence,
+        }
+    }
+
+    // Lists up to `limit` transactions the address is involved in, skipping the first
+    // `from`. Pending transactions come first, followed by the confirmed ones from the
+    // newest to the oldest.
+    pub fn address_history(
+        &self,
+        addr: &PublicKey,
+        from: usize,
+        limit: usize,
+    ) -> Vec<HistoryEntry> {
+        let pending = self
+            .pending_transactions
+            .iter()
+            .rev()
+            .filter(|transaction| transaction.involves(addr))
+            .map(|transaction| HistoryEntry {
+                txid: transaction.id(),
+                height: None,
+                transaction: transaction.clone(),
+            });
+
+        let confirmed = self
+            .index
+            .locations(addr)
+            .iter()
+            .rev()
+            .filter_map(|location| {
+                self.chain
+                    .get(location.height)
+                    .and_then(|block| block.body.transaction_at(location.position))
+                    .map(|transaction| HistoryEntry {
+                        txid: transaction.id(),
+                        height: Some(location.height),
+                        transaction: transaction.clone(),
+                    })
+            });
+
+        pending.chain(confirmed).skip(from).take(limit).collect()
+    }
+
+    // Checks whether the chain is valid or not: the headers have to link up and carry
+    // valid proofs of work, and commit to the transactions of their blocks, which have
+    // to be signed and paid for. Every block pays out the reward once.
+    pub fn validate_chain(&self) -> Result<(), BlockError> {
+        let headers = self.headers(0, self.chain.len());
+        Self::validate_header_chain(None, &headers, self.spec.difficulty)?;
+
+        for block in &self.chain {
+            block.validate_body(self.spec.reward)?;
+        }
+
+        AddressIndex::build(&self.chain).map(|_| ())
     }
 
-    // Finds a nonce that satisfies the mining condition for the next block.
-    // Note: it doesn't depend on the contents of the block that being added.
-    pub fn find_nonce(previous_nonce: Nonce, previous_hash: &Option<Digest>) -> Nonce {
-        // If there is no previous hash, then the block isn't getting added onto a chain
-        let mut nonce = 0;
-        while !Blockchain::is_valid_nonce(previous_nonce, nonce, previous_hash) {
-            if nonce % 1000 == 0 {
-                println!("Searching for nonce {}", nonce);
+    // Checks that a run of headers links up and carries valid proofs of work.
+    // The anchor is the header the run builds on, or None if it starts at the genesis block.
+    pub fn validate_header_chain(
+        anchor: Option<&BlockHeader>,
+        headers: &[BlockHeader],
+        difficulty: usize,
+    ) -> Result<(), BlockError> {
+        let mut previous = anchor;
+
+        for header in headers {
+            // Proof of work is checked first, since it's what makes a header expensive
+            // to come up with. A header without one is never taken for a sign that
+            // we're behind.
+            if !header.meets_difficulty(difficulty) {
+                return Err(BlockError::BadPow { height: header.ind });
             }
-            nonce += 1;
+
+            let (ind, previous_hash) = match previous {
+                Some(previous) => (previous.ind + 1, Some(previous.hash())),
+                None => (0, None),
+            };
+
+            if header.version != BLOCK_VERSION {
+                return Err(BlockError::UnsupportedVersion {
+                    height: header.ind,
+                    version: header.version,
+                });
+            }
+
+            if header.ind != ind {
+                return Err(BlockError::BadHeight {
+                    height: header.ind,
+                    expected: ind,
+                });
+            }
+
+            if header.previous_hash != previous_hash {
+                return Err(BlockError::BadPrevHash { height: header.ind });
+            }
+
+            previous = Some(header);
         }
 
-        println!("Nonce found: {}", nonce);
-        nonce
+        Ok(())
     }
 
-    pub fn get_last_nonce(&self) -> Nonce {
-        // There will always be at least one block, so it's safe to unwrap
-        let last_block = self.chain.last().unwrap();
+    // Finds a nonce with which the header satisfies the mining condition
+    pub fn find_nonce(header: &BlockHeader, difficulty: usize) -> Nonce {
+        let mut header = header.clone();
+        header.nonce = 0;
+
+        while !header.meets_difficulty(difficulty) {
+            header.nonce += 1;
+        }
 
-        last_block.nonce
+        debug!("Found nonce {} for block {}", header.nonce, header.ind);
+        header.nonce
     }
 
     // Broadcast the transaction to each peer in the peer list
     pub fn broadcast_transaction(&self, transaction: Transaction) {
-        let client = reqwest::Client::new();
-
-        let mut headers = Headers::new();
-        headers.set(ContentType::json());
-        for peer in &self.peers {
-            let serialized_transaction = serde_json::to_string(&transaction).unwrap();
-            client
-                .post(peer)
-                .headers(headers.clone())
-                .body(serialized_transaction)
-                .send();
-        }
+        self.post_to_peers(
+            "/network/transactions/new",
+            serde_json::to_string(&transaction).unwrap(),
+        );
+
+        self.announce(Message::Transaction(transaction));
+    }
+
+    // Broadcast a new block to each peer in the peer list
+    pub fn broadcast_block(&self, block: Block) {
+        self.post_to_peers(
+            "/network/blocks/new",
+            serde_json::to_string(&block).unwrap(),
+        );
+
+        self.announce(Message::NewBlock(block));
+    }
+
+    // Posts to the peers that don't speak the binary protocol. This happens on a thread
+    // of its own, since peers relay blocks back to us while we'd still be holding on
+    // to the chain otherwise.
+    fn post_to_peers(&self, path: &str, body: String) {
+        let urls: Vec<String> = self
+            .peers
+            .iter()
+            .filter(|peer| !self.wire_peers.contains_key(*peer))
+            .map(|peer| format!("{}{}", peer, path))
+            .collect();
+
+        thread::spawn(move || {
+            let client = reqwest::Client::new();
+
+            let mut headers = Headers::new();
+            headers.set(ContentType::json());
+            for url in urls {
+                let _ = client
+                    .post(&url)
+                    .headers(headers.clone())
+                    .body(body.clone())
+                    .send();
+            }
+        });
+    }
+
+    // Sends a message to the peers that speak the binary protocol
+    fn announce(&self, message: Message) {
+        let wire_peers = self.wire_peers.values().cloned().collect();
+        wire::announce(
+            wire_peers,
+            self.spec.magic,
+            self.chain.len(),
+            self.p2p_port,
+            message,
+        );
+    }
+
+    // Counts and logs a block that's turned down
+    fn reject_block(&mut self, block: &Block, err: &BlockError) {
+        self.metrics.reject_block(err.reason());
+        log_event!(
+            Level::Warn,
+            "Rejected block",
+            height = block.header.ind,
+            hash = hex::encode(&block.hash()),
+            reason = err.reason(),
+            error = err,
+        );
+    }
+
+    // Counts and logs a transaction that's turned down
+    fn reject_transaction(&mut self, transaction: &Transaction, err: &TxError) {
+        self.metrics.reject_transaction(err.reason());
+        log_event!(
+            Level::Warn,
+            "Rejected transaction",
+            txid = hex::encode(&transaction.id()),
+            reason = err.reason(),
+            error = err,
+        );
     }
 }
@@ -0,0 +1,216 @@
+use super::blockchain::{BlockError, Blockchain, NodeAddr};
+use super::spec::ChainSpec;
+use super::store::ChainStore;
+use super::sync::{self, SyncError};
+use super::{JoinRequest, JoinResponse, ServerConfig};
+
+use reqwest::header::{ContentType, Headers};
+use std::cmp::min;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use serde_json;
+use reqwest;
+
+// Pause after the first round of failed join attempts. It doubles after every
+// following round, up to the maximum.
+const INITIAL_BACKOFF_MS: u64 = 500;
+const MAX_BACKOFF_MS: u64 = 30_000;
+
+// Exit codes, following the conventions of sysexits.h
+const EX_DATAERR: i32 = 65;
+const EX_UNAVAILABLE: i32 = 69;
+const EX_PROTOCOL: i32 = 76;
+
+pub struct BootstrapOptions {
+    // Nodes to join the network through, in order of preference
+    pub seeds: Vec<NodeAddr>,
+
+    // Number of rounds over all seeds before giving up
+    pub attempts: u32,
+
+    // Snapshot to start from if the network can't be joined
+    pub snapshot: Option<PathBuf>,
+
+    pub store: Option<ChainStore>,
+
+    // The network to join
+    pub spec: ChainSpec,
+}
+
+#[derive(Debug)]
+pub enum BootstrapError {
+    // The persisted chain couldn't be loaded
+    Store(io::Error),
+    // The persisted chain's transactions don't add up
+    InvalidStore(BlockError),
+    // The snapshot to fall back to couldn't be loaded
+    Snapshot(io::Error),
+    // The snapshot to fall back to doesn't hold a valid chain
+    InvalidSnapshot(BlockError),
+    // The persisted chain or the snapshot belongs to another network
+    GenesisMismatch,
+    // There were no attempts left to join the network
+    Unreachable,
+    // A request to a seed node failed
+    Request(NodeAddr),
+    // A seed node turned down the join request
+    Rejected(NodeAddr),
+    // A seed node belongs to another network
+    NetworkMismatch(NodeAddr),
+    // A seed node's response couldn't be read
+    Read(NodeAddr),
+    // A seed node's response couldn't be deserialized
+    Deserialize(NodeAddr),
+    // The chain couldn't be synchronized from a seed node and its peers
+    Sync(SyncError),
+}
+
+impl BootstrapError {
+    // Exit code for the node process. Problems with local data won't go away by
+    // restarting the node, whereas unreachable seeds might.
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            BootstrapError::Store(_)
+            | BootstrapError::InvalidStore(_)
+            | BootstrapError::Snapshot(_)
+            | BootstrapError::InvalidSnapshot(_)
+            | BootstrapError::GenesisMismatch => EX_DATAERR,
+            BootstrapError::Unreachable | BootstrapError::Request(_) => EX_UNAVAILABLE,
+            BootstrapError::Rejected(_)
+            | BootstrapError::NetworkMismatch(_)
+            | BootstrapError::Read(_)
+            | BootstrapError::Deserialize(_)
+            | BootstrapError::Sync(_) => EX_PROTOCOL,
+        }
+    }
+}
+
+impl fmt::Display for BootstrapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BootstrapError::Store(ref err) => {
+                write!(f, "couldn't load the persisted chain: {}", err)
+            }
+            BootstrapError::InvalidStore(ref err) => {
+                write!(f, "the persisted chain is invalid: {}", err)
+            }
+            BootstrapError::Snapshot(ref err) => write!(f, "couldn't load the snapshot: {}", err),
+            BootstrapError::InvalidSnapshot(ref err) => {
+                write!(f, "the snapshot holds an invalid chain: {}", err)
+            }
+            BootstrapError::GenesisMismatch => {
+                write!(f, "the stored chain has a different genesis block")
+            }
+            BootstrapError::Unreachable => write!(f, "no seed node could be reached"),
+            BootstrapError::Request(ref seed) => write!(f, "couldn't make a request to {}", seed),
+            BootstrapError::Rejected(ref seed) => write!(f, "{} rejected the join request", seed),
+            BootstrapError::NetworkMismatch(ref seed) => {
+                write!(f, "{} belongs to a different network", seed)
+            }
+            BootstrapError::Read(ref seed) => write!(f, "couldn't read the response of {}", seed),
+            BootstrapError::Deserialize(ref seed) => {
+                write!(f, "couldn't deserialize the response of {}", seed)
+            }
+            BootstrapError::Sync(ref err) => write!(f, "couldn't synchronize the chain: {}", err),
+        }
+    }
+}
+
+// Joins the network through the first seed that responds, and synchronizes the chain
+// from it and its peers. The seeds are tried in rounds, pausing for longer after
+// every round that fails. Returns the last error if all attempts fail.
+pub fn join_network(
+    chain: &mut Blockchain,
+    seeds: &[NodeAddr],
+    attempts: u32,
+    config: &ServerConfig,
+) -> Result<(), BootstrapError> {
+    let client = reqwest::Client::new();
+    let mut backoff = INITIAL_BACKOFF_MS;
+    let mut last_err = BootstrapError::Unreachable;
+
+    for attempt in 0..attempts {
+        if attempt > 0 {
+            info!("Retrying to join the network in {}ms", backoff);
+            thread::sleep(Duration::from_millis(backoff));
+            backoff = min(backoff * 2, MAX_BACKOFF_MS);
+        }
+
+        for seed in seeds {
+            match join_through(&client, chain, seed, config) {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    warn!("Couldn't join the network through {}: {}", seed, err);
+                    last_err = err;
+                }
+            }
+        }
+    }
+
+    Err(last_err)
+}
+
+fn join_through(
+    client: &reqwest::Client,
+    chain: &mut Blockchain,
+    seed: &NodeAddr,
+    config: &ServerConfig,
+) -> Result<(), BootstrapError> {
+    let join_url = format!("{}/network/join", seed);
+    let request = JoinRequest {
+        network: chain.spec.network.clone(),
+        genesis_hash: chain.genesis_hash(),
+        node: config.clone(),
+    };
+    let serialized_request = serde_json::to_string(&request).unwrap();
+
+    let mut headers = Headers::new();
+    headers.set(ContentType::json());
+    let mut response = client
+        .post(&join_url)
+        .headers(headers)
+        .body(serialized_request)
+        .send()
+        .map_err(|_| BootstrapError::Request(seed.clone()))?;
+
+    if !response.status().is_success() {
+        return Err(BootstrapError::Rejected(seed.clone()));
+    }
+
+    let text = response
+        .text()
+        .map_err(|_| BootstrapError::Read(seed.clone()))?;
+    let join_response: JoinResponse =
+        serde_json::from_str(&text).map_err(|_| BootstrapError::Deserialize(seed.clone()))?;
+
+    // The seed checks this too, but might be misconfigured
+    if join_response.network != request.network
+        || join_response.genesis_hash != request.genesis_hash
+    {
+        return Err(BootstrapError::NetworkMismatch(seed.clone()));
+    }
+
+    // The seed's peers can serve blocks too
+    let mut sources = vec![seed.clone()];
+    sources.extend(join_response.peers.iter().cloned());
+
+    let added = sync::sync_chain(chain, &sources).map_err(BootstrapError::Sync)?;
+    info!("Synchronized {} blocks from the network", added);
+
+    // Talk to the seed over the binary protocol, if it speaks it
+    let seed_host = reqwest::Url::parse(seed)
+        .ok()
+        .and_then(|url| url.host_str().map(String::from));
+    if let (Some(host), Some(p2p_port)) = (seed_host, join_response.p2p_port) {
+        chain.register_wire_peer(seed.clone(), format!("{}:{}", host, p2p_port));
+    }
+
+    for peer in sources {
+        chain.register_peer(peer);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,73 @@
+// Where a chain gets the current time from. Nodes use the system's clock, while tests
+// and simulations set the time themselves, so that the blocks they mine come out the
+// same on every run.
+
+use chrono::prelude::*;
+use chrono::Duration;
+use std::sync::{Arc, Mutex};
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+impl<C: Clock> Clock for Arc<C> {
+    fn now(&self) -> DateTime<Utc> {
+        (**self).now()
+    }
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+// A clock that stands still until it's set or advanced
+pub struct ManualClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+impl ManualClock {
+    pub fn new(now: DateTime<Utc>) -> ManualClock {
+        ManualClock {
+            now: Mutex::new(now),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+        *now = *now + duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
+
+// A clock as held by a chain, which is the system's unless another one is given
+#[derive(Clone)]
+pub struct SharedClock(Arc<Clock>);
+
+impl SharedClock {
+    pub fn new<C: Clock + 'static>(clock: C) -> SharedClock {
+        SharedClock(Arc::new(clock))
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        self.0.now()
+    }
+}
+
+impl Default for SharedClock {
+    fn default() -> SharedClock {
+        SharedClock::new(SystemClock)
+    }
+}
@@ -0,0 +1,242 @@
+use super::address::{self, AddressError};
+use super::logging::{LogError, LogFilter, LogFormat};
+use super::spec::ChainSpec;
+use super::PublicKey;
+
+use reqwest;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use toml;
+
+// Configuration of a node, as read from its TOML config file. Every setting has a
+// default, and can be overridden on the command line.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    // Directory the chain is persisted in, if any
+    pub data_dir: Option<PathBuf>,
+
+    // Address and port the http api listens on
+    pub address: String,
+    pub port: u16,
+
+    // Port the node accepts binary protocol connections on, if any
+    pub p2p_port: Option<u16>,
+
+    // Nodes to join the network through, in order of preference
+    pub seeds: Vec<String>,
+
+    // Number of rounds over the seeds before giving up
+    pub attempts: u32,
+
+    // Chain snapshot to start from if the network can't be joined
+    pub snapshot: Option<PathBuf>,
+
+    // Chain spec file of the network, defaults to the dev network
+    pub chain: Option<PathBuf>,
+
+    // Name of the network the node expects to be on. Guards against starting the
+    // node with the wrong spec file.
+    pub network: Option<String>,
+
+    pub wallet: Option<PathBuf>,
+
+    // Only follow the block headers, and check the wallet's transactions with proofs
+    pub light: bool,
+
+    pub mining: MiningConfig,
+
+    // Level to log at, optionally followed by levels for single modules, as in
+    // "info,tulipchain::sync=debug"
+    pub log_level: String,
+
+    // Either "text" or "json", with one object per line
+    pub log_format: String,
+
+    pub api: ApiConfig,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MiningConfig {
+    // Whether to mine blocks in the background, rather than only when asked to
+    pub enabled: bool,
+    pub threads: usize,
+
+    // Address the rewards are paid to, defaults to the wallet's main address
+    pub reward_addr: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    // Token that requests to the node's wallet and miner have to carry, as in
+    // `Authorization: Bearer <token>`. Without one, these endpoints are open.
+    pub token: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    // Mining needs at least one thread
+    Threads,
+    RewardAddress(AddressError),
+    LogLevel(LogError),
+    LogFormat(String),
+    // A seed isn't a url
+    Seed(String),
+    // The binary protocol can't share its port with the http api
+    Ports(u16),
+    // The spec file describes another network than the configured one
+    Network { expected: String, found: String },
+    // A light node has no blocks to mine on
+    LightMining,
+    EmptyToken,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref err) => write!(f, "couldn't read the config file: {}", err),
+            ConfigError::Parse(ref err) => write!(f, "couldn't parse the config file: {}", err),
+            ConfigError::Threads => write!(f, "mining needs at least one thread"),
+            ConfigError::RewardAddress(ref err) => write!(f, "invalid reward address: {}", err),
+            ConfigError::LogLevel(ref err) => write!(f, "invalid log level: {}", err),
+            ConfigError::LogFormat(ref err) => write!(f, "{}", err),
+            ConfigError::Seed(ref seed) => write!(f, "seed '{}' isn't a url", seed),
+            ConfigError::Ports(port) => {
+                write!(
+                    f,
+                    "the api and the binary protocol can't both use port {}",
+                    port
+                )
+            }
+            ConfigError::Network {
+                ref expected,
+                ref found,
+            } => write!(
+                f,
+                "expected the {} network, but the chain spec is for {}",
+                expected, found
+            ),
+            ConfigError::LightMining => write!(f, "a light node can't mine"),
+            ConfigError::EmptyToken => write!(f, "the api token can't be empty"),
+        }
+    }
+}
+
+impl Default for NodeConfig {
+    fn default() -> NodeConfig {
+        NodeConfig {
+            data_dir: None,
+            address: String::from("localhost"),
+            port: 8000,
+            p2p_port: None,
+            seeds: vec![],
+            attempts: 5,
+            snapshot: None,
+            chain: None,
+            network: None,
+            wallet: None,
+            light: false,
+            mining: MiningConfig::default(),
+            log_level: String::from("info"),
+            log_format: String::from("text"),
+            api: ApiConfig::default(),
+        }
+    }
+}
+
+impl Default for MiningConfig {
+    fn default() -> MiningConfig {
+        MiningConfig {
+            enabled: false,
+            threads: 1,
+            reward_addr: None,
+        }
+    }
+}
+
+impl NodeConfig {
+    pub fn load(path: &Path) -> Result<NodeConfig, ConfigError> {
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(ConfigError::Io)?;
+
+        NodeConfig::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<NodeConfig, ConfigError> {
+        toml::from_str(contents).map_err(ConfigError::Parse)
+    }
+
+    // Checks the settings that can be checked on their own, once the overrides from
+    // the command line have been applied
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.mining.threads == 0 {
+            return Err(ConfigError::Threads);
+        }
+
+        self.reward_addr()?;
+
+        self.log_filter()?;
+        self.log_format()?;
+
+        for seed in &self.seeds {
+            if reqwest::Url::parse(seed).is_err() {
+                return Err(ConfigError::Seed(seed.clone()));
+            }
+        }
+
+        if self.p2p_port == Some(self.port) {
+            return Err(ConfigError::Ports(self.port));
+        }
+
+        if self.light && self.mining.enabled {
+            return Err(ConfigError::LightMining);
+        }
+
+        if self
+            .api
+            .token
+            .as_ref()
+            .map_or(false, |token| token.is_empty())
+        {
+            return Err(ConfigError::EmptyToken);
+        }
+
+        Ok(())
+    }
+
+    // Checks that the spec is for the network the node is meant to be on
+    pub fn check_network(&self, spec: &ChainSpec) -> Result<(), ConfigError> {
+        match self.network {
+            Some(ref network) if *network != spec.network => Err(ConfigError::Network {
+                expected: network.clone(),
+                found: spec.network.clone(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn log_filter(&self) -> Result<LogFilter, ConfigError> {
+        LogFilter::parse(&self.log_level).map_err(ConfigError::LogLevel)
+    }
+
+    pub fn log_format(&self) -> Result<LogFormat, ConfigError> {
+        self.log_format.parse().map_err(ConfigError::LogFormat)
+    }
+
+    pub fn reward_addr(&self) -> Result<Option<PublicKey>, ConfigError> {
+        match self.mining.reward_addr {
+            Some(ref encoded) => address::decode(encoded)
+                .map(Some)
+                .map_err(ConfigError::RewardAddress),
+            None => Ok(None),
+        }
+    }
+}
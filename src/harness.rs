@@ -0,0 +1,261 @@
+// Runs a network of full nodes inside the test process, each serving its http api on
+// an ephemeral localhost port, so that gossip, joining and fork resolution can be
+// tested end to end
+
+use super::full_node;
+use auth::ApiAuth;
+use tulipchain::address::Address;
+use tulipchain::blockchain::{BlockHeader, Blockchain, NodeAddr, SharedChain};
+use tulipchain::bootstrap;
+use tulipchain::transaction::Transaction;
+use tulipchain::wallet::Wallet;
+use tulipchain::{Digest, PublicKey, ServerConfig, Tulips};
+
+use reqwest;
+use reqwest::header::{ContentType, Headers};
+use rocket;
+use rocket::config::{Config, Environment};
+use serde_json;
+use std::collections::HashSet;
+use std::net::TcpListener;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How long a node gets to start, and the network gets to converge
+const TIMEOUT_SECS: u64 = 10;
+const POLL_INTERVAL_MS: u64 = 20;
+
+pub struct Node {
+    pub url: NodeAddr,
+    pub config: ServerConfig,
+    pub chain: SharedChain,
+
+    // The wallet's main address, which mining rewards are paid to
+    pub address: Address,
+}
+
+impl Node {
+    pub fn height(&self) -> usize {
+        self.chain.read().unwrap().chain.len() - 1
+    }
+
+    pub fn tip(&self) -> Digest {
+        self.chain.read().unwrap().chain.last().unwrap().hash()
+    }
+
+    pub fn balance(&self, addr: &PublicKey) -> Tulips {
+        self.chain.read().unwrap().balance(addr).confirmed
+    }
+
+    pub fn has_pending(&self, transaction: &Transaction) -> bool {
+        self.chain
+            .read()
+            .unwrap()
+            .pending_transactions
+            .contains(transaction)
+    }
+}
+
+pub struct Network {
+    pub nodes: Vec<Node>,
+    client: reqwest::Client,
+
+    // Peers of every node from before the network was partitioned
+    partitioned: Option<Vec<HashSet<NodeAddr>>>,
+}
+
+impl Network {
+    // Starts `count` nodes on the development network, which don't know each other yet
+    pub fn start(count: usize) -> Network {
+        let client = reqwest::Client::new();
+        let nodes = (0..count).map(|_| start_node(&client)).collect();
+
+        Network {
+            nodes,
+            client,
+            partitioned: None,
+        }
+    }
+
+    // Starts `count` nodes, all of which join the network through the first one
+    pub fn connected(count: usize) -> Network {
+        let network = Network::start(count);
+        for node in 1..count {
+            network.join(node, 0);
+        }
+
+        network
+    }
+
+    // Has a node join the network through another one, as `--connect` would
+    pub fn join(&self, node: usize, seed: usize) {
+        let node = &self.nodes[node];
+        let seeds = vec![self.nodes[seed].url.clone()];
+        let mut writer = node.chain.write().unwrap();
+
+        bootstrap::join_network(&mut writer, &seeds, 1, &node.config).unwrap();
+    }
+
+    // Mines a block through a node's api, which announces it to the node's peers
+    pub fn mine(&self, node: usize) -> BlockHeader {
+        let url = format!("{}/mine", self.nodes[node].url);
+        let mut response = self.client.get(&url).send().unwrap();
+        assert!(response.status().is_success());
+
+        response.json().unwrap()
+    }
+
+    // Submits a signed transaction to a node, which relays it to its peers. Returns
+    // whether the node accepted it.
+    pub fn submit(&self, node: usize, transaction: &Transaction) -> bool {
+        let mut headers = Headers::new();
+        headers.set(ContentType::json());
+
+        self.client
+            .post(&format!("{}/transactions/submit", self.nodes[node].url))
+            .headers(headers)
+            .body(serde_json::to_string(transaction).unwrap())
+            .send()
+            .unwrap()
+            .status()
+            .is_success()
+    }
+
+    // Pays from the main address of one node to the main address of another
+    pub fn pay(&self, from: usize, to: usize, value: Tulips) -> Transaction {
+        let node = &self.nodes[from];
+        let recipient = self.nodes[to].address.public_key;
+        let sequence = node
+            .chain
+            .read()
+            .unwrap()
+            .balance(&node.address.public_key)
+            .next_sequence;
+        let transaction = node.address.new_transaction(value, recipient, sequence);
+        assert!(self.submit(from, &transaction));
+
+        transaction
+    }
+
+    // Cuts the given nodes off from the others. Nodes on either side only hear from
+    // nodes on their own side, until the network is healed.
+    pub fn partition(&mut self, side: &[usize]) {
+        assert!(self.partitioned.is_none());
+
+        let urls: Vec<NodeAddr> = self.nodes.iter().map(|node| node.url.clone()).collect();
+        let mut peers = vec![];
+        for (i, node) in self.nodes.iter().enumerate() {
+            let mut writer = node.chain.write().unwrap();
+            peers.push(writer.peers.clone());
+
+            let across: Vec<&NodeAddr> = urls
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| side.contains(&i) != side.contains(&j))
+                .map(|(_, url)| url)
+                .collect();
+            writer.peers.retain(|peer| !across.contains(&peer));
+        }
+
+        self.partitioned = Some(peers);
+    }
+
+    // Reconnects the sides of a partition. The nodes only converge once a new block
+    // reaches the ones on the shorter chain, which then catch up with their peers.
+    pub fn heal(&mut self) {
+        let peers = self
+            .partitioned
+            .take()
+            .expect("the network isn't partitioned");
+
+        for (node, peers) in self.nodes.iter().zip(peers) {
+            node.chain.write().unwrap().peers = peers;
+        }
+    }
+
+    // Waits for the condition to hold, failing the test if it doesn't in time
+    pub fn wait_until<F: Fn(&Network) -> bool>(&self, what: &str, condition: F) {
+        let started = Instant::now();
+        while !condition(self) {
+            if started.elapsed() > Duration::from_secs(TIMEOUT_SECS) {
+                panic!("timed out waiting until {}", what);
+            }
+            thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+        }
+    }
+
+    pub fn is_converged(&self) -> bool {
+        let tip = self.nodes[0].tip();
+        self.nodes.iter().all(|node| node.tip() == tip)
+    }
+
+    // Waits for every node to agree on the tip, and returns its height
+    pub fn assert_converged(&self) -> usize {
+        self.wait_until("the nodes agree on the tip", |network| {
+            network.is_converged()
+        });
+
+        self.nodes[0].height()
+    }
+}
+
+fn start_node(client: &reqwest::Client) -> Node {
+    let port = free_port();
+    let config = ServerConfig {
+        address: String::from("127.0.0.1"),
+        port,
+        p2p_port: None,
+    };
+    let url = format!("http://{}:{}", config.address, port);
+
+    let wallet = Wallet::generate();
+    let address = wallet.address(0);
+    let mut chain = Blockchain::new();
+    chain.address = Some(address.clone());
+    let chain: SharedChain = Arc::new(RwLock::new(chain));
+
+    let server = Config::build(Environment::Development)
+        .address(config.address.clone())
+        .port(port)
+        .finalize()
+        .unwrap();
+    let rocket = full_node(
+        rocket::custom(server, false),
+        chain.clone(),
+        wallet,
+        ApiAuth(None),
+    );
+    thread::spawn(move || rocket.launch());
+
+    let node = Node {
+        url,
+        config,
+        chain,
+        address,
+    };
+
+    let started = Instant::now();
+    while client
+        .get(&format!("{}/chain/tip", node.url))
+        .send()
+        .is_err()
+    {
+        if started.elapsed() > Duration::from_secs(TIMEOUT_SECS) {
+            panic!("node on port {} didn't start", port);
+        }
+        thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+    }
+
+    node
+}
+
+// Asks the OS for a port nothing listens on. It's only free again once the listener
+// is dropped, so another process could take it in between, which is unlikely enough.
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
@@ -0,0 +1,116 @@
+// Hexadecimal encoding of digests and signatures, as they appear in urls and JSON
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+const DIGITS: &[u8] = b"0123456789abcdef";
+
+pub fn encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len() * 2);
+    for &byte in bytes {
+        encoded.push(DIGITS[(byte >> 4) as usize] as char);
+        encoded.push(DIGITS[(byte & 0xf) as usize] as char);
+    }
+
+    encoded
+}
+
+// Decodes a string of hex digits, in either case. Returns None if it isn't one.
+pub fn decode(encoded: &str) -> Option<Vec<u8>> {
+    if encoded.len() % 2 != 0 {
+        return None;
+    }
+
+    let digits: Option<Vec<u8>> = encoded
+        .chars()
+        .map(|c| c.to_digit(16).map(|digit| digit as u8))
+        .collect();
+
+    digits.map(|digits| {
+        digits
+            .chunks(2)
+            .map(|pair| (pair[0] << 4) | pair[1])
+            .collect()
+    })
+}
+
+// Byte strings that are written in hex in human readable formats like JSON
+pub trait Hex: Sized {
+    fn to_hex(&self) -> String;
+    fn from_hex(encoded: &str) -> Option<Self>;
+}
+
+impl Hex for Vec<u8> {
+    fn to_hex(&self) -> String {
+        encode(self)
+    }
+
+    fn from_hex(encoded: &str) -> Option<Vec<u8>> {
+        decode(encoded)
+    }
+}
+
+// Use with `#[serde(with = "hex")]`. Binary formats keep the bytes as they are, so
+// the block encoding isn't affected.
+pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Hex + Serialize,
+    S: Serializer,
+{
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&value.to_hex())
+    } else {
+        value.serialize(serializer)
+    }
+}
+
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: Hex + Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    if deserializer.is_human_readable() {
+        let encoded = String::deserialize(deserializer)?;
+        T::from_hex(&encoded).ok_or_else(|| de::Error::custom("invalid hex string"))
+    } else {
+        T::deserialize(deserializer)
+    }
+}
+
+// Use with `#[serde(with = "hex::option")]` on optional byte strings
+pub mod option {
+    use super::Hex;
+
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<T, S>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Hex + Serialize,
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            value
+                .as_ref()
+                .map(|value| value.to_hex())
+                .serialize(serializer)
+        } else {
+            value.serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        T: Hex + Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            match Option::<String>::deserialize(deserializer)? {
+                Some(encoded) => T::from_hex(&encoded)
+                    .map(Some)
+                    .ok_or_else(|| de::Error::custom("invalid hex string")),
+                None => Ok(None),
+            }
+        } else {
+            Option::<T>::deserialize(deserializer)
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use super::blockchain::{Block, BlockError};
+use super::hex;
+use super::transaction::{Transaction, TxError};
+use super::{Digest, PublicKey, Tulips};
+
+use std::collections::HashMap;
+
+// Where a transaction sits in the chain: the height of its block, and its position
+// in the block's merkle tree, where the coinbase comes first
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TxLocation {
+    pub height: usize,
+    pub position: usize,
+}
+
+// Looks up the transactions and the confirmed balance of an address without scanning
+// the chain. It's kept up to date as blocks are added to the chain.
+#[derive(Debug, Clone, Default)]
+pub struct AddressIndex {
+    locations: HashMap<PublicKey, Vec<TxLocation>>,
+    balances: HashMap<PublicKey, Tulips>,
+
+    // Sequence number of the next transaction each sender can make
+    sequences: HashMap<PublicKey, u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Balance {
+    // Balance according to the transactions in the chain
+    pub confirmed: Tulips,
+
+    // Balance once the pending transactions are confirmed too
+    pub pending: Tulips,
+
+    // What can be spent right away: the confirmed balance minus pending payments.
+    // Tulips that are still on their way in can't be spent yet.
+    pub available: Tulips,
+
+    // Sequence number the address' next transaction has to carry, counting the
+    // pending ones
+    pub next_sequence: u64,
+}
+
+// A transaction an address is involved in, as listed in its history
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct HistoryEntry {
+    #[serde(with = "hex")]
+    pub txid: Digest,
+
+    // Height of the block holding the transaction, or None if it's pending
+    pub height: Option<usize>,
+    pub transaction: Transaction,
+}
+
+impl AddressIndex {
+    // Indexes the blocks of a chain in order, checking that they add up
+    pub fn build<'a, I: IntoIterator<Item = &'a Block>>(
+        chain: I,
+    ) -> Result<AddressIndex, BlockError> {
+        let mut index = AddressIndex::default();
+        for block in chain {
+            index.add_block(block)?;
+        }
+
+        Ok(index)
+    }
+
+    // Applies the block's transactions to the ledger. Senders have to afford their
+    // payments, counting the ones earlier in the block, and use their sequence numbers
+    // in order. A block that doesn't add up is turned down and leaves the index as it
+    // was.
+    pub fn add_block(&mut self, block: &Block) -> Result<(), BlockError> {
+        let height = block.header.ind;
+        let invalid = |error| BlockError::BadTransaction { height, error };
+
+        // Balances and sequence numbers the block changes, which are only applied
+        // once all of its transactions check out
+        let mut balances: HashMap<PublicKey, Tulips> = HashMap::new();
+        let mut sequences: HashMap<PublicKey, u64> = HashMap::new();
+        for transaction in block.body.all_transactions() {
+            if let Some(sender_addr) = transaction.sender_addr {
+                let expected = sequences
+                    .get(&sender_addr)
+                    .cloned()
+                    .unwrap_or_else(|| self.next_sequence(&sender_addr));
+                if transaction.sequence != expected {
+                    return Err(invalid(TxError::BadSequence {
+                        expected,
+                        sequence: transaction.sequence,
+                    }));
+                }
+
+                let available = balances
+                    .get(&sender_addr)
+                    .cloned()
+                    .unwrap_or_else(|| self.balance(&sender_addr));
+                let remaining = available.checked_sub(transaction.value).ok_or_else(|| {
+                    invalid(TxError::InsufficientFunds {
+                        available,
+                        value: transaction.value,
+                    })
+                })?;
+
+                balances.insert(sender_addr, remaining);
+                sequences.insert(sender_addr, expected + 1);
+            }
+
+            let recipient_addr = transaction.recipient_addr;
+            let received = balances
+                .get(&recipient_addr)
+                .cloned()
+                .unwrap_or_else(|| self.balance(&recipient_addr));
+            balances.insert(recipient_addr, received.saturating_add(transaction.value));
+        }
+
+        self.balances.extend(balances);
+        self.sequences.extend(sequences);
+
+        for (position, transaction) in block.body.all_transactions().into_iter().enumerate() {
+            let location = TxLocation { height, position };
+
+            self.locations
+                .entry(transaction.recipient_addr)
+                .or_insert_with(Vec::new)
+                .push(location);
+
+            // A transfer to oneself is listed once
+            if let Some(sender_addr) = transaction.sender_addr {
+                if sender_addr != transaction.recipient_addr {
+                    self.locations
+                        .entry(sender_addr)
+                        .or_insert_with(Vec::new)
+                        .push(location);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Locations of the address' transactions, oldest first
+    pub fn locations(&self, addr: &PublicKey) -> &[TxLocation] {
+        match self.locations.get(addr) {
+            Some(locations) => locations,
+            None => &[],
+        }
+    }
+
+    pub fn balance(&self, addr: &PublicKey) -> Tulips {
+        self.balances.get(addr).cloned().unwrap_or(0)
+    }
+
+    // Sequence number of the address' next transaction, going by the chain alone
+    pub fn next_sequence(&self, addr: &PublicKey) -> u64 {
+        self.sequences.get(addr).cloned().unwrap_or(0)
+    }
+}
@@ -0,0 +1,48 @@
+// Where new keypairs come from. Addresses are normally made from the operating
+// system's randomness, but can be derived from a seed instead, so that tests and
+// simulations end up with the same addresses, and so the same blocks, on every run.
+
+use super::{PrivateKey, PublicKey};
+
+use sodiumoxide::crypto::hash::sha256;
+use sodiumoxide::crypto::sign;
+
+pub trait KeySource {
+    fn keypair(&mut self) -> (PublicKey, PrivateKey);
+}
+
+pub struct OsKeys;
+
+impl KeySource for OsKeys {
+    fn keypair(&mut self) -> (PublicKey, PrivateKey) {
+        sign::gen_keypair()
+    }
+}
+
+// Derives the n-th keypair from the sha256 digest of the seed followed by n
+pub struct SeededKeys {
+    seed: Vec<u8>,
+    next: u64,
+}
+
+impl SeededKeys {
+    pub fn new(seed: &[u8]) -> SeededKeys {
+        SeededKeys {
+            seed: seed.to_vec(),
+            next: 0,
+        }
+    }
+}
+
+impl KeySource for SeededKeys {
+    fn keypair(&mut self) -> (PublicKey, PrivateKey) {
+        let mut input = self.seed.clone();
+        for shift in (0..8).rev() {
+            input.push((self.next >> (shift * 8)) as u8);
+        }
+        self.next += 1;
+
+        let sha256::Digest(digest) = sha256::hash(&input);
+        sign::keypair_from_seed(&sign::Seed(digest))
+    }
+}
@@ -0,0 +1,103 @@
+// Tulipchain's core: blocks and transactions along with their validation, wallets,
+// and the protocols nodes keep their chains in sync with. The node and the command
+// line client are binaries built on top of it.
+
+extern crate bincode;
+extern crate chrono;
+#[macro_use]
+extern crate log;
+extern crate rand;
+extern crate reqwest;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate sodiumoxide;
+extern crate toml;
+
+// Logs an event with fields, like
+// log_event!(Level::Info, "Accepted block", height = 12, hash = hash)
+#[macro_export]
+macro_rules! log_event {
+    ($level:expr, $message:expr, $($key:ident = $value:expr),+ $(,)*) => {{
+        let level = $level;
+        if $crate::logging::enabled(level, module_path!()) {
+            $crate::logging::log_event(
+                level,
+                module_path!(),
+                $message,
+                vec![$((stringify!($key), $value.to_string())),+],
+            );
+        }
+    }};
+}
+
+pub mod address;
+pub mod base58;
+pub mod blockchain;
+pub mod bootstrap;
+pub mod clock;
+pub mod config;
+pub mod hex;
+pub mod index;
+pub mod keys;
+pub mod light;
+pub mod logging;
+pub mod merkle;
+pub mod metrics;
+pub mod miner;
+pub mod sim;
+pub mod spec;
+pub mod status;
+pub mod store;
+pub mod sync;
+pub mod transaction;
+pub mod wallet;
+pub mod wire;
+mod wordlist;
+
+use sodiumoxide::crypto::sign;
+use std::collections::HashSet;
+use blockchain::NodeAddr;
+
+pub type PublicKey = sign::ed25519::PublicKey;
+pub type PrivateKey = sign::ed25519::SecretKey;
+
+pub type SignedDigest = Vec<u8>;
+pub type Digest = Vec<u8>;
+pub type Tulips = u32;
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ServerConfig {
+    pub address: String,
+    pub port: u16,
+
+    // Port the node accepts binary protocol connections on, if any
+    pub p2p_port: Option<u16>,
+}
+
+// Sent by a node asking to join the network
+#[derive(Deserialize, Serialize)]
+pub struct JoinRequest {
+    // Identify the network the node belongs to
+    pub network: String,
+    #[serde(with = "hex")]
+    pub genesis_hash: Digest,
+
+    pub node: ServerConfig,
+}
+
+// Sent in response to a node joining the network
+#[derive(Deserialize, Serialize)]
+pub struct JoinResponse {
+    pub network: String,
+    #[serde(with = "hex")]
+    pub genesis_hash: Digest,
+
+    // Peers the joining node can download blocks from
+    pub peers: HashSet<NodeAddr>,
+    pub height: usize,
+
+    // Port the node that was joined accepts binary protocol connections on, if any
+    pub p2p_port: Option<u16>,
+}
@@ -0,0 +1,210 @@
+use super::blockchain::{BlockHeader, Blockchain, NodeAddr};
+use super::{address, hex};
+use super::merkle::ProvenTransaction;
+use super::spec::ChainSpec;
+use super::sync::{self, Source, SyncError};
+use super::wallet::SharedWallet;
+use super::{Digest, PublicKey, Tulips};
+
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+use reqwest;
+
+// Pause between two rounds of catching up with the full nodes
+const SYNC_INTERVAL_SECS: u64 = 30;
+
+// The light chain as shared between the http handlers and the sync thread
+pub type SharedLightChain = Arc<RwLock<LightChain>>;
+
+// Follows the chain by its headers only. Instead of storing blocks, it keeps the
+// wallet's transactions along with proofs that they're part of the chain.
+#[derive(Clone)]
+pub struct LightChain {
+    pub headers: Vec<BlockHeader>,
+
+    // Full nodes the headers and proofs are downloaded from
+    pub peers: Vec<NodeAddr>,
+
+    // Transactions involving the wallet's addresses, checked against the headers
+    pub transactions: Vec<ProvenTransaction>,
+
+    pub spec: ChainSpec,
+}
+
+// What a light node knows about its wallet
+#[derive(Serialize, Deserialize)]
+pub struct LightStatus {
+    pub height: usize,
+    #[serde(with = "hex")]
+    pub tip: Digest,
+    pub balance: Tulips,
+    pub transactions: Vec<ProvenTransaction>,
+}
+
+impl LightChain {
+    // Starts a light chain at the network's genesis block
+    pub fn new(spec: ChainSpec, peers: Vec<NodeAddr>) -> LightChain {
+        let genesis = spec.genesis_block();
+
+        LightChain {
+            headers: vec![genesis.header],
+            peers,
+            transactions: vec![],
+            spec,
+        }
+    }
+
+    // Downloads the headers that were added since the last sync, checking that they
+    // link up and carry valid proofs of work. Returns the number of new headers.
+    pub fn sync_headers(&mut self) -> Result<usize, SyncError> {
+        let source = match self.peers.first() {
+            Some(peer) => peer.clone(),
+            None => return Ok(0),
+        };
+
+        let client = reqwest::Client::new();
+        let (fork_height, new_headers) = {
+            let headers = &self.headers;
+            sync::new_headers(
+                &client,
+                &Source::Http(source),
+                headers.len(),
+                |height| headers.get(height).map(|header| header.hash()),
+                self.spec.difficulty,
+            )?
+        };
+
+        let added = new_headers.len();
+        if added > 0 {
+            self.headers.truncate(fork_height);
+            self.headers.extend(new_headers);
+        }
+
+        Ok(added)
+    }
+
+    // Fetches the transactions the addresses are involved in from a full node, and
+    // keeps them if their proofs check out against our headers. Transactions in blocks
+    // we don't have the headers of yet are left for the next sync.
+    pub fn check_transactions(&mut self, addrs: &[PublicKey]) -> Result<(), SyncError> {
+        let source = match self.peers.first() {
+            Some(peer) => peer.clone(),
+            None => return Ok(()),
+        };
+
+        let client = reqwest::Client::new();
+        let mut transactions: Vec<ProvenTransaction> = vec![];
+
+        for addr in addrs {
+            let url = format!("{}/network/proofs/{}", source, address::encode(addr));
+            let proven: Vec<ProvenTransaction> = sync::fetch(&client, &url)?;
+
+            for proven_transaction in proven {
+                if proven_transaction.proof.height >= self.headers.len() {
+                    continue;
+                }
+
+                if !proven_transaction.transaction.involves(addr)
+                    || !self.is_proven(&proven_transaction)
+                {
+                    return Err(SyncError::InvalidProof);
+                }
+
+                // Transactions between two of our addresses are served for both. Each
+                // is told apart by its block and its id, which the proof covers, so
+                // rewards paid to the same address in different blocks all count.
+                let known = {
+                    let proof = &proven_transaction.proof;
+                    transactions.iter().any(|known| {
+                        known.proof.height == proof.height && known.proof.txid == proof.txid
+                    })
+                };
+                if !known {
+                    transactions.push(proven_transaction);
+                }
+            }
+        }
+
+        self.transactions = transactions;
+
+        Ok(())
+    }
+
+    // Checks that a transaction is part of the block its proof points at
+    pub fn is_proven(&self, proven_transaction: &ProvenTransaction) -> bool {
+        let proof = &proven_transaction.proof;
+        let header = match self.headers.get(proof.height) {
+            Some(header) => header,
+            None => return false,
+        };
+
+        proven_transaction.transaction.id() == proof.txid
+            && header.hash() == proof.block_hash
+            && header.merkle_root == proof.merkle_root
+            && proof.verify()
+    }
+
+    // Balance of the addresses according to the proven transactions
+    pub fn balance(&self, addrs: &[PublicKey]) -> Tulips {
+        let mut received: Tulips = 0;
+        let mut sent: Tulips = 0;
+
+        for proven_transaction in &self.transactions {
+            let transaction = &proven_transaction.transaction;
+            if addrs.contains(&transaction.recipient_addr) {
+                received = received.saturating_add(transaction.value);
+            }
+
+            if let Some(ref sender_addr) = transaction.sender_addr {
+                if addrs.contains(sender_addr) {
+                    sent = sent.saturating_add(transaction.value);
+                }
+            }
+        }
+
+        received.saturating_sub(sent)
+    }
+
+    pub fn status(&self, addrs: &[PublicKey]) -> LightStatus {
+        let tip = self.headers.last().unwrap();
+
+        LightStatus {
+            height: tip.ind,
+            tip: tip.hash(),
+            balance: self.balance(addrs),
+            transactions: self.transactions.clone(),
+        }
+    }
+}
+
+// Keeps the light chain and the wallet's transactions up to date in the background.
+// The network is queried on a copy of the chain, so readers aren't blocked meanwhile.
+pub fn follow(chain: SharedLightChain, wallet: SharedWallet) {
+    thread::spawn(move || loop {
+        let mut light = chain.read().unwrap().clone();
+        let addrs: Vec<PublicKey> = wallet
+            .read()
+            .unwrap()
+            .addresses()
+            .iter()
+            .map(|address| address.public_key)
+            .collect();
+
+        let result = light
+            .sync_headers()
+            .and_then(|added| light.check_transactions(&addrs).map(|_| added));
+
+        match result {
+            Ok(added) => {
+                if added > 0 {
+                    info!("Synchronized {} headers from the network", added);
+                }
+                *chain.write().unwrap() = light;
+            }
+            Err(err) => warn!("Couldn't synchronize with the network: {}", err),
+        }
+
+        thread::sleep(Duration::from_secs(SYNC_INTERVAL_SECS));
+    });
+}
@@ -0,0 +1,219 @@
+// Logging for the node's binaries. Records go to stderr, either as lines of text or
+// as one JSON object per line. Which records are written is decided by a filter like
+// "info,tulipchain::sync=debug": a default level, followed by levels for modules.
+// Events logged with log_event! carry fields like the height of a block, which JSON
+// records hold as keys of their own.
+
+use chrono::prelude::*;
+use log::{self, Level, LevelFilter, Log, Metadata, Record};
+use serde_json::{self, Map, Value};
+use std::cell::RefCell;
+use std::cmp;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+#[derive(Debug, PartialEq)]
+pub enum LogError {
+    // A level in the filter isn't one of error, warn, info, debug, trace or off
+    Level(String),
+    // A logger was set up already
+    Installed,
+}
+
+impl fmt::Display for LogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LogError::Level(ref level) => write!(
+                f,
+                "unknown log level '{}', expected one of error, warn, info, debug, trace and off",
+                level
+            ),
+            LogError::Installed => write!(f, "logging was set up already"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<LogFormat, String> {
+        match format {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "unknown log format '{}', expected text or json",
+                format
+            )),
+        }
+    }
+}
+
+// Levels to log at, by module
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFilter {
+    default: LevelFilter,
+
+    // Module paths with their levels, the most specific first
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl LogFilter {
+    pub fn parse(filter: &str) -> Result<LogFilter, LogError> {
+        let parse_level = |level: &str| {
+            LevelFilter::from_str(level.trim()).map_err(|_| LogError::Level(level.to_string()))
+        };
+
+        let mut default = LevelFilter::Info;
+        let mut modules = vec![];
+        for directive in filter
+            .split(',')
+            .filter(|directive| !directive.trim().is_empty())
+        {
+            let mut parts = directive.splitn(2, '=');
+            let first = parts.next().unwrap().trim();
+            match parts.next() {
+                Some(level) => modules.push((first.to_string(), parse_level(level)?)),
+                None => default = parse_level(first)?,
+            }
+        }
+
+        modules.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+
+        Ok(LogFilter { default, modules })
+    }
+
+    // Level of the most specific module the target is part of
+    pub fn level(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .find(|&&(ref module, _)| {
+                target == module || target.starts_with(&format!("{}::", module))
+            })
+            .map(|&(_, level)| level)
+            .unwrap_or(self.default)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|&(_, level)| level)
+            .fold(self.default, cmp::max)
+    }
+}
+
+// Keys and values describing an event, like ("height", "12")
+pub type Fields = Vec<(&'static str, String)>;
+
+thread_local! {
+    // Fields of the event that's being logged on this thread
+    static FIELDS: RefCell<Fields> = RefCell::new(vec![]);
+}
+
+struct Logger {
+    filter: LogFilter,
+    format: LogFormat,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = FIELDS.with(|fields| {
+            format_record(
+                record.level(),
+                record.target(),
+                record.args(),
+                &fields.borrow(),
+                self.format,
+            )
+        });
+        let stderr = io::stderr();
+        let _ = writeln!(stderr.lock(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+// Whether a record of the level for the target would be written
+pub fn enabled(level: Level, target: &str) -> bool {
+    level <= log::max_level()
+        && log::logger().enabled(&Metadata::builder().level(level).target(target).build())
+}
+
+// Logs an event along with its fields. Use log_event! rather than calling this.
+pub fn log_event(level: Level, target: &str, message: &str, fields: Fields) {
+    FIELDS.with(|current| *current.borrow_mut() = fields);
+    log::logger().log(
+        &Record::builder()
+            .level(level)
+            .target(target)
+            .args(format_args!("{}", message))
+            .build(),
+    );
+    FIELDS.with(|current| current.borrow_mut().clear());
+}
+
+pub fn format_record(
+    level: Level,
+    target: &str,
+    args: &fmt::Arguments,
+    fields: &[(&'static str, String)],
+    format: LogFormat,
+) -> String {
+    let time = Utc::now().to_rfc3339();
+
+    match format {
+        LogFormat::Text => {
+            let mut line = format!("{} {:<5} {}: {}", time, level, target, args);
+            if !fields.is_empty() {
+                line.push(':');
+            }
+            for &(key, ref value) in fields {
+                // Values that would run into the next field are quoted
+                if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"') {
+                    line.push_str(&format!(" {}={:?}", key, value));
+                } else {
+                    line.push_str(&format!(" {}={}", key, value));
+                }
+            }
+
+            line
+        }
+        LogFormat::Json => {
+            let mut record = Map::new();
+            for &(key, ref value) in fields {
+                record.insert(key.to_string(), Value::String(value.clone()));
+            }
+            record.insert(String::from("time"), Value::String(time));
+            record.insert(String::from("level"), Value::from(level.as_str()));
+            record.insert(String::from("target"), Value::from(target));
+            record.insert(String::from("message"), Value::String(args.to_string()));
+
+            serde_json::to_string(&record).unwrap()
+        }
+    }
+}
+
+// Installs the logger, which can only be done once
+pub fn init(filter: LogFilter, format: LogFormat) -> Result<(), LogError> {
+    let max_level = filter.max_level();
+    log::set_boxed_logger(Box::new(Logger { filter, format })).map_err(|_| LogError::Installed)?;
+    log::set_max_level(max_level);
+
+    Ok(())
+}
@@ -1,167 +1,956 @@
 #![feature(plugin)]
 #![plugin(rocket_codegen)]
 extern crate argparse;
-extern crate bincode;
-extern crate chrono;
-extern crate rand;
+#[macro_use]
+extern crate log;
 extern crate reqwest;
 extern crate rocket;
 extern crate rocket_contrib;
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
-extern crate serde_json;
 extern crate sodiumoxide;
+extern crate tulipchain;
+
+#[cfg(test)]
+extern crate chrono;
+#[cfg(test)]
+#[macro_use]
+extern crate proptest;
+#[cfg(test)]
+extern crate rand;
+#[cfg(test)]
+extern crate serde_json;
 
-mod transaction;
-mod blockchain;
-mod address;
+mod auth;
+mod timing;
 
+#[cfg(test)]
+mod harness;
 #[cfg(test)]
 mod test;
 
-use transaction::{PartialTransaction, Transaction};
-use argparse::{ArgumentParser, Store};
-use sodiumoxide::crypto::sign;
-use std::collections::HashSet;
-use blockchain::Blockchain;
-use rocket::fairing::AdHoc;
+use tulipchain::transaction::{
+    PartialTransaction, SignablePayload, Transaction, TxError, UnsignedTransaction,
+};
+use tulipchain::{address, hex, light, logging, metrics, miner, sync, wire};
+use tulipchain::{Digest, JoinRequest, JoinResponse, PublicKey, ServerConfig};
+use argparse::{ArgumentParser, Collect, Store, StoreConst, StoreOption, StoreTrue};
+use std::cmp::min;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use tulipchain::blockchain::{Block, BlockError, BlockHeader, Blockchain, NodeAddr, SharedChain};
+use tulipchain::store::ChainStore;
+use tulipchain::spec::ChainSpec;
+use tulipchain::bootstrap::BootstrapOptions;
+use tulipchain::config::NodeConfig;
+use auth::{ApiAuth, Authorized};
+use tulipchain::wallet::{SharedWallet, Wallet, WalletError};
+use tulipchain::merkle::{MerkleProof, ProvenTransaction};
+use tulipchain::index::{Balance, HistoryEntry};
+use tulipchain::light::{LightChain, LightStatus, SharedLightChain};
+use tulipchain::status::NodeStatus;
+use tulipchain::metrics::{RequestMetrics, SharedRequestMetrics};
+use timing::RequestTimer;
+use rocket::config::{Config, ConfigError, Environment};
+use rocket::http::{Accept, Status};
+use rocket::request::Request;
+use rocket::response::content::Html;
+use rocket::response::{Failure, Responder, Response};
 use rocket_contrib::Json;
-use std::sync::RwLock;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex, RwLock};
 use rocket::State;
 
-type PublicKey = sign::ed25519::PublicKey;
-type PrivateKey = sign::ed25519::SecretKey;
+// Exit code for an unusable configuration, following sysexits.h
+const EX_CONFIG: i32 = 78;
 
-type SignedDigest = Vec<u8>;
-type Digest = Vec<u8>;
-type Tulips = u32;
+// Number of entries listed when a request doesn't say
+const DEFAULT_PAGE_LIMIT: usize = 20;
 
-#[derive(Deserialize, Serialize)]
-pub struct ServerConfig {
-    address: String,
-    port: u16,
+// Endpoint that lists the peers the node gossips with
+#[get("/network/peers")]
+fn peers(blockchain: State<SharedChain>) -> Json<Vec<NodeAddr>> {
+    let mut peers: Vec<NodeAddr> = blockchain.read().unwrap().peers.iter().cloned().collect();
+    peers.sort();
+
+    Json(peers)
 }
 
 // Endpoint that returns the full serialized chain of that node's blockchain
 #[get("/blockchain/full")]
-fn full_blockchain(blockchain: State<RwLock<Blockchain>>) -> Json<Blockchain> {
+fn full_blockchain(blockchain: State<SharedChain>) -> Json<Blockchain> {
     Json(blockchain.read().unwrap().clone())
 }
 
-#[post("/network/join", data = "<addr>")]
-fn join(blockchain: State<RwLock<Blockchain>>, addr: Json<ServerConfig>) -> Json<Blockchain> {
-    // Clone the blockchain before adding the new node to the peer list
-    let mut to_transmit = blockchain.read().unwrap().clone();
+#[post("/network/join", data = "<request>")]
+fn join(
+    blockchain: State<SharedChain>,
+    request: Json<JoinRequest>,
+) -> Result<Json<JoinResponse>, Failure> {
+    let request = request.into_inner();
+    let mut block_writer = blockchain.write().unwrap();
 
-    // Empty the peer list before transmitting the blockchain.
-    to_transmit.peers = HashSet::new();
+    // Nodes of other networks aren't let in
+    if request.network != block_writer.spec.network
+        || request.genesis_hash != block_writer.genesis_hash()
+    {
+        return Err(Failure(Status::Forbidden));
+    }
 
-    // Add the source address to the list of peers
-    let mut block_writer = blockchain.write().unwrap();
+    // Hand out the peers before adding the new node to the list. The chain itself is
+    // downloaded separately, in batches.
+    let response = JoinResponse {
+        network: request.network,
+        genesis_hash: request.genesis_hash,
+        peers: block_writer.peers.clone(),
+        height: block_writer.chain.len(),
+        p2p_port: block_writer.p2p_port,
+    };
 
-    let source_config = addr.into_inner();
+    // Add the source address to the list of peers
+    let source_config = request.node;
     let mut source_str = format!("{}:{}", source_config.address, source_config.port);
     if !source_str.contains("http://") {
         source_str = format!("http://{}", source_str);
     }
 
+    if let Some(p2p_port) = source_config.p2p_port {
+        let wire_addr = format!("{}:{}", source_config.address, p2p_port);
+        block_writer.register_wire_peer(source_str.clone(), wire_addr);
+    }
+
     block_writer.register_peer(source_str);
 
-    Json(to_transmit)
+    Ok(Json(response))
+}
+
+// Endpoint that serves a range of block headers to synchronizing nodes
+#[get("/network/headers/<from>/<count>")]
+fn headers(blockchain: State<SharedChain>, from: usize, count: usize) -> Json<Vec<BlockHeader>> {
+    let count = min(count, sync::MAX_HEADERS);
+
+    Json(blockchain.read().unwrap().headers(from, count))
+}
+
+// Endpoint that serves a range of full blocks to synchronizing nodes
+#[get("/network/blocks/<from>/<count>")]
+fn blocks(blockchain: State<SharedChain>, from: usize, count: usize) -> Json<Vec<Block>> {
+    let count = min(count, sync::MAX_BLOCKS);
+
+    Json(blockchain.read().unwrap().blocks(from, count))
+}
+
+// Range of entries requested from a listing
+#[derive(FromForm)]
+struct Page {
+    from: Option<usize>,
+    limit: Option<usize>,
+}
+
+// Sent in response to a request for the chain's tip
+#[derive(Deserialize, Serialize)]
+pub struct ChainTip {
+    height: usize,
+    #[serde(with = "hex")]
+    hash: Digest,
+
+    // Expected number of hashes it took to build the chain
+    work: u64,
+}
+
+// Endpoint that lists a page of blocks, in order of height
+#[get("/blocks?<page>")]
+fn blocks_page(blockchain: State<SharedChain>, page: Page) -> Json<Vec<Block>> {
+    let from = page.from.unwrap_or(0);
+    let limit = min(page.limit.unwrap_or(DEFAULT_PAGE_LIMIT), sync::MAX_BLOCKS);
+
+    Json(blockchain.read().unwrap().blocks(from, limit))
+}
+
+// The first page of blocks. Routes with a query only match requests that have one.
+#[get("/blocks", rank = 2)]
+fn first_blocks_page(blockchain: State<SharedChain>) -> Json<Vec<Block>> {
+    blocks_page(
+        blockchain,
+        Page {
+            from: None,
+            limit: None,
+        },
+    )
+}
+
+// Endpoint that serves the block at the given height
+#[get("/blocks/<height>")]
+fn block_at(blockchain: State<SharedChain>, height: usize) -> Option<Json<Block>> {
+    blockchain
+        .read()
+        .unwrap()
+        .chain
+        .get(height)
+        .cloned()
+        .map(Json)
+}
+
+// Endpoint that serves the header of the block at the given height
+#[get("/blocks/<height>/header")]
+fn block_header(blockchain: State<SharedChain>, height: usize) -> Option<Json<BlockHeader>> {
+    blockchain.read().unwrap().header_at(height).map(Json)
+}
+
+fn find_block(blockchain: &SharedChain, hash: &str) -> Result<Option<Json<Block>>, Failure> {
+    let hash = match hex::decode(hash) {
+        Some(hash) => hash,
+        None => return Err(Failure(Status::BadRequest)),
+    };
+
+    Ok(blockchain
+        .read()
+        .unwrap()
+        .block_by_hash(&hash)
+        .cloned()
+        .map(Json))
+}
+
+// Endpoint that serves a block, given the hex encoded hash of its header. Heights
+// are matched first, and no hash is short enough to be mistaken for one.
+#[get("/blocks/<hash>", rank = 2)]
+fn block_by_hash(
+    blockchain: State<SharedChain>,
+    hash: String,
+) -> Result<Option<Json<Block>>, Failure> {
+    find_block(&blockchain, &hash)
+}
+
+#[get("/blocks/hash/<hash>", rank = 2)]
+fn block_by_hash_path(
+    blockchain: State<SharedChain>,
+    hash: String,
+) -> Result<Option<Json<Block>>, Failure> {
+    find_block(&blockchain, &hash)
+}
+
+// Endpoint that describes the tip of the chain
+#[get("/chain/tip")]
+fn chain_tip(blockchain: State<SharedChain>) -> Json<ChainTip> {
+    let reader = blockchain.read().unwrap();
+    let tip = reader.chain.last().unwrap();
+
+    Json(ChainTip {
+        height: tip.header.ind,
+        hash: tip.hash(),
+        work: reader.cumulative_work(),
+    })
+}
+
+// Sent in response to a transaction or block that's turned down
+#[derive(Deserialize, Serialize)]
+pub struct ErrorBody {
+    // Short name of the reason, e.g. insufficient_funds
+    error: String,
+    message: String,
+}
+
+// A transaction or block that's turned down, answered with a status fitting the reason
+#[derive(Debug)]
+enum Rejection {
+    Transaction(TxError),
+    Block(BlockError),
+}
+
+impl From<TxError> for Rejection {
+    fn from(err: TxError) -> Rejection {
+        Rejection::Transaction(err)
+    }
 }
 
-// Endpoint to receive new transactions from the network
+impl From<BlockError> for Rejection {
+    fn from(err: BlockError) -> Rejection {
+        Rejection::Block(err)
+    }
+}
+
+impl<'r> Responder<'r> for Rejection {
+    fn respond_to(self, request: &Request) -> Result<Response<'r>, Status> {
+        let status = match self {
+            // The payment is well formed, but the sender can't afford it right now
+            Rejection::Transaction(TxError::InsufficientFunds { .. }) => {
+                Status::UnprocessableEntity
+            }
+            // The sender's sequence number is taken, e.g. by the very same transaction
+            Rejection::Transaction(TxError::BadSequence { .. }) => Status::Conflict,
+            Rejection::Transaction(_) => Status::BadRequest,
+            // The block may be valid, but doesn't build on our tip
+            Rejection::Block(BlockError::BadHeight { .. })
+            | Rejection::Block(BlockError::BadPrevHash { .. }) => Status::Conflict,
+            Rejection::Block(_) => Status::BadRequest,
+        };
+
+        let body = match self {
+            Rejection::Transaction(ref err) => ErrorBody {
+                error: err.reason().to_string(),
+                message: err.to_string(),
+            },
+            Rejection::Block(ref err) => ErrorBody {
+                error: err.reason().to_string(),
+                message: err.to_string(),
+            },
+        };
+
+        Response::build_from(Json(body).respond_to(request)?)
+            .status(status)
+            .ok()
+    }
+}
+
+// Endpoint to receive new transactions from peers that don't speak the binary protocol
 #[post("/network/transactions/new", data = "<transaction_data>")]
 fn new_transaction_from_network(
-    blockchain: State<RwLock<Blockchain>>,
+    blockchain: State<SharedChain>,
     transaction_data: Json<Transaction>,
-) {
-    let transaction = transaction_data.into_inner();
-    let mut block_writer = blockchain.write().unwrap();
+) -> Result<(), Rejection> {
+    sync::receive_transaction(&blockchain, transaction_data.into_inner())?;
 
-    block_writer.append_transaction(transaction);
+    Ok(())
 }
 
 #[post("/transactions/new", data = "<transaction_data>")]
 fn new_transaction(
-    blockchain: State<RwLock<Blockchain>>,
+    _auth: Authorized,
+    blockchain: State<SharedChain>,
     transaction_data: Json<PartialTransaction>,
-) -> Json<Option<Transaction>> {
+) -> Result<Json<Transaction>, Rejection> {
     let partial_transaction = transaction_data.into_inner();
     let mut block_writer = blockchain.write().unwrap();
-    let mut node_addr = block_writer.address.clone().unwrap();
 
-    let transaction = node_addr.new_transaction(
-        partial_transaction.value,
-        partial_transaction.recipient_addr,
+    let transaction = {
+        let address = block_writer.address.as_ref().unwrap();
+        let sequence = block_writer.balance(&address.public_key).next_sequence;
+
+        address.new_transaction(
+            partial_transaction.value,
+            partial_transaction.recipient_addr,
+            sequence,
+        )
+    };
+
+    // The ledger turns down payments the node can't afford
+    block_writer.append_transaction(transaction.clone())?;
+    block_writer.broadcast_transaction(transaction.clone());
+
+    Ok(Json(transaction))
+}
+
+// Endpoint that hands clients the payload to sign for a payment of their own
+#[post("/transactions/payload", data = "<transaction_data>")]
+fn signable_payload(transaction_data: Json<UnsignedTransaction>) -> Json<SignablePayload> {
+    let unsigned = transaction_data.into_inner();
+    let transaction = Transaction::new(
+        Some(unsigned.sender_addr),
+        unsigned.recipient_addr,
+        unsigned.value,
+        unsigned.sequence,
     );
 
-    if let None = transaction {
-        return Json(None);
-    }
+    Json(SignablePayload {
+        payload: transaction.signable_payload(),
+        transaction,
+    })
+}
 
-    if !block_writer.append_transaction(transaction.clone().unwrap()) {
-        return Json(None);
-    }
+// Sent in response to a transaction that was accepted
+#[derive(Deserialize, Serialize)]
+pub struct SubmittedTransaction {
+    #[serde(with = "hex")]
+    txid: Digest,
+}
+
+// Endpoint that accepts transactions signed by clients, and relays them to the peers.
+// Coinbase transactions and bad signatures are rejected outright, while transactions
+// the sender can't afford are unprocessable.
+#[post("/transactions/submit", data = "<transaction_data>")]
+fn submit_transaction(
+    blockchain: State<SharedChain>,
+    transaction_data: Json<Transaction>,
+) -> Result<Json<SubmittedTransaction>, Rejection> {
+    let transaction = transaction_data.into_inner();
 
-    block_writer.broadcast_transaction(transaction.clone().unwrap());
+    // Signatures are checked before the chain is locked
+    transaction.verify_digest()?;
 
-    Json(transaction)
+    let mut block_writer = blockchain.write().unwrap();
+    block_writer.append_transaction(transaction.clone())?;
+
+    let txid = transaction.id();
+    block_writer.broadcast_transaction(transaction);
+
+    Ok(Json(SubmittedTransaction { txid }))
 }
 
+// Endpoint that looks up a transaction, pending or confirmed, by its hex encoded id
+#[get("/transactions/<txid>")]
+fn transaction_by_id(
+    blockchain: State<SharedChain>,
+    txid: String,
+) -> Result<Option<Json<HistoryEntry>>, Failure> {
+    let txid = match hex::decode(&txid) {
+        Some(txid) => txid,
+        None => return Err(Failure(Status::BadRequest)),
+    };
+
+    Ok(blockchain.read().unwrap().find_transaction(&txid).map(Json))
+}
+
+// Endpoint that proves that a transaction is part of a block, given its hex encoded id
+#[get("/transactions/<txid>/proof")]
+fn transaction_proof(
+    blockchain: State<SharedChain>,
+    txid: String,
+) -> Result<Option<Json<MerkleProof>>, Failure> {
+    let txid = match hex::decode(&txid) {
+        Some(txid) => txid,
+        None => return Err(Failure(Status::BadRequest)),
+    };
+
+    Ok(blockchain
+        .read()
+        .unwrap()
+        .transaction_proof(&txid)
+        .map(Json))
+}
+
+// Endpoint that proves every transaction an address is involved in. Light nodes use
+// it to check their wallet's transactions.
+#[get("/network/proofs/<addr>")]
+fn address_proofs(
+    blockchain: State<SharedChain>,
+    addr: String,
+) -> Result<Json<Vec<ProvenTransaction>>, Failure> {
+    let addr = match address::decode(&addr) {
+        Ok(addr) => addr,
+        Err(_) => return Err(Failure(Status::BadRequest)),
+    };
+
+    Ok(Json(blockchain.read().unwrap().address_proofs(&addr)))
+}
+
+// Endpoint that tells the confirmed and pending balance of an address
+#[get("/addresses/<addr>/balance")]
+fn address_balance(blockchain: State<SharedChain>, addr: String) -> Result<Json<Balance>, Failure> {
+    let addr = match address::decode(&addr) {
+        Ok(addr) => addr,
+        Err(_) => return Err(Failure(Status::BadRequest)),
+    };
+
+    Ok(Json(blockchain.read().unwrap().balance(&addr)))
+}
+
+// Endpoint that lists a page of the transactions an address is involved in, the
+// pending ones first and then the confirmed ones from the newest
+#[get("/addresses/<addr>/transactions?<page>")]
+fn address_history(
+    blockchain: State<SharedChain>,
+    addr: String,
+    page: Page,
+) -> Result<Json<Vec<HistoryEntry>>, Failure> {
+    let addr = match address::decode(&addr) {
+        Ok(addr) => addr,
+        Err(_) => return Err(Failure(Status::BadRequest)),
+    };
+
+    let from = page.from.unwrap_or(0);
+    let limit = min(page.limit.unwrap_or(DEFAULT_PAGE_LIMIT), sync::MAX_BLOCKS);
+
+    Ok(Json(
+        blockchain
+            .read()
+            .unwrap()
+            .address_history(&addr, from, limit),
+    ))
+}
+
+#[get("/addresses/<addr>/transactions", rank = 2)]
+fn first_address_history(
+    blockchain: State<SharedChain>,
+    addr: String,
+) -> Result<Json<Vec<HistoryEntry>>, Failure> {
+    address_history(
+        blockchain,
+        addr,
+        Page {
+            from: None,
+            limit: None,
+        },
+    )
+}
+
+// Endpoint that mines a block holding the pending transactions and announces it
 #[get("/mine")]
-fn mine_block(blockchain: State<RwLock<Blockchain>>) -> String {
-    String::from("Mining a new block")
+fn mine_block(_auth: Authorized, blockchain: State<SharedChain>) -> Json<BlockHeader> {
+    let mut block_writer = blockchain.write().unwrap();
+
+    let block = block_writer.mine();
+    let header = block.header.clone();
+    block_writer.broadcast_block(block);
+
+    Json(header)
+}
+
+// Endpoint to receive new blocks from peers that don't speak the binary protocol
+#[post("/network/blocks/new", data = "<block>")]
+fn add_block(blockchain: State<SharedChain>, block: Json<Block>) -> Result<(), Rejection> {
+    sync::receive_block(&blockchain, block.into_inner())?;
+
+    Ok(())
 }
 
-// Endpoint to report new nonce
-#[get("/blocks/new")]
-fn add_block(blockchain: State<RwLock<Blockchain>>) -> String {
-    String::from("Mining a new block")
+// Endpoint that lists the receiving addresses handed out by the node's wallet
+#[get("/wallet/addresses")]
+fn wallet_addresses(_auth: Authorized, wallet: State<SharedWallet>) -> Json<Vec<String>> {
+    let addresses = wallet.read().unwrap().addresses();
+
+    Json(
+        addresses
+            .iter()
+            .map(|address| address.to_string())
+            .collect(),
+    )
 }
 
+// Endpoint that hands out a fresh receiving address, e.g. one per payment
+#[post("/wallet/addresses")]
+fn new_wallet_address(
+    _auth: Authorized,
+    wallet: State<SharedWallet>,
+) -> Result<Json<String>, Failure> {
+    let mut wallet_writer = wallet.write().unwrap();
+    let address = wallet_writer.new_address();
+
+    // An address that isn't saved can't be recovered without scanning the chain
+    if let Err(err) = wallet_writer.persist() {
+        error!("Couldn't save the wallet: {}", err);
+        return Err(Failure(Status::InternalServerError));
+    }
+
+    Ok(Json(address.to_string()))
+}
+
+// Endpoint of light nodes that reports the wallet's balance and proven transactions
+#[get("/light/status")]
+fn light_status(
+    _auth: Authorized,
+    chain: State<SharedLightChain>,
+    wallet: State<SharedWallet>,
+) -> Json<LightStatus> {
+    let addrs: Vec<PublicKey> = wallet
+        .read()
+        .unwrap()
+        .addresses()
+        .iter()
+        .map(|address| address.public_key)
+        .collect();
+
+    Json(chain.read().unwrap().status(&addrs))
+}
+
+// The status as either a page or JSON, depending on what the client accepts
+enum StatusPage {
+    Html(NodeStatus),
+    Json(NodeStatus),
+}
+
+impl<'r> Responder<'r> for StatusPage {
+    fn respond_to(self, request: &Request) -> Result<Response<'r>, Status> {
+        match self {
+            StatusPage::Html(status) => Html(status.to_html()).respond_to(request),
+            StatusPage::Json(status) => Json(status).respond_to(request),
+        }
+    }
+}
+
+// Endpoint scraped by Prometheus
+#[get("/metrics")]
+fn prometheus_metrics(chain: State<SharedChain>, requests: State<SharedRequestMetrics>) -> String {
+    metrics::render(&chain.read().unwrap(), &requests.lock().unwrap())
+}
+
+// Endpoint that describes the node. Browsers get a page, other clients get JSON.
 #[get("/")]
-fn index(blockchain: State<RwLock<Blockchain>>) -> String {
-    unimplemented!()
+fn index(blockchain: State<SharedChain>, accept: Option<&Accept>) -> StatusPage {
+    let status = NodeStatus::of(&blockchain.read().unwrap());
+
+    // Wildcards don't count, so that clients like curl get JSON
+    let wants_html = accept.map_or(false, |accept| {
+        accept.media_types().any(|media_type| media_type.is_html())
+    });
+
+    if wants_html {
+        StatusPage::Html(status)
+    } else {
+        StatusPage::Json(status)
+    }
 }
 
-fn main() {
-    let mut base_addr = String::new();
-    {
-        let mut ap = ArgumentParser::new();
-        ap.set_description("Tulipchain runner");
-        ap.refer(&mut base_addr).add_option(
-            &["--connect"],
-            Store,
-            "node address to connect to (base url)",
+// Sets up the node's wallet, from the seed phrase if one is given. Without a wallet
+// file, the node gets a throwaway wallet.
+fn open_wallet(path: Option<PathBuf>, phrase: &str) -> Result<Wallet, WalletError> {
+    if !phrase.is_empty() {
+        let mut wallet = Wallet::from_phrase(phrase)?;
+        if let Some(path) = path {
+            wallet.set_path(path);
+        }
+
+        return Ok(wallet);
+    }
+
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(Wallet::generate()),
+    };
+
+    let (wallet, created) = Wallet::open(path)?;
+    // The phrase is for the operator's eyes only, so it's kept out of the logs
+    if created {
+        println!(
+            "Created a new wallet. Write down its seed phrase to be able to recover it: {}",
+            wallet.phrase()
         );
-        ap.parse_args_or_exit();
     }
 
-    rocket::ignite()
-        .attach(AdHoc::on_attach(move |rocket| {
-            let config = rocket.config().clone();
-            let server_config = ServerConfig {
-                address: config.address,
-                port: config.port,
-            };
+    Ok(wallet)
+}
+
+// Runs a light node, which follows the chain through the seeds without storing blocks
+fn launch_light(
+    rocket: rocket::Rocket,
+    spec: ChainSpec,
+    seeds: Vec<NodeAddr>,
+    wallet: Wallet,
+    auth: ApiAuth,
+) {
+    if seeds.is_empty() {
+        error!("Couldn't start the node: a light node needs full nodes to connect to");
+        exit(EX_CONFIG);
+    }
 
-            let chain: RwLock<Blockchain> =
-                Blockchain::init_chain(base_addr.clone(), &server_config);
-            return Ok(rocket.manage(chain));
-        }))
+    info!("Starting light node on the {} network", spec.network);
+    let chain: SharedLightChain = Arc::new(RwLock::new(LightChain::new(spec, seeds)));
+    let wallet: SharedWallet = Arc::new(RwLock::new(wallet));
+    light::follow(chain.clone(), wallet.clone());
+
+    rocket
+        .manage(chain)
+        .manage(wallet)
+        .manage(auth)
+        .mount(
+            "/",
+            routes![light_status, wallet_addresses, new_wallet_address],
+        )
+        .launch();
+}
+
+// Sets up the http api of a full node. Mining rewards go to the chain's reward address.
+fn full_node(
+    rocket: rocket::Rocket,
+    chain: SharedChain,
+    wallet: Wallet,
+    auth: ApiAuth,
+) -> rocket::Rocket {
+    let requests: SharedRequestMetrics = Arc::new(Mutex::new(RequestMetrics::default()));
+
+    rocket
+        .manage(chain)
+        .manage(Arc::new(RwLock::new(wallet)))
+        .manage(auth)
         .manage(reqwest::Client::new())
+        .manage(requests.clone())
+        .attach(RequestTimer(requests))
         .mount(
             "/",
             routes![
                 index,
+                prometheus_metrics,
                 join,
+                peers,
+                headers,
+                blocks,
+                blocks_page,
+                first_blocks_page,
+                block_at,
+                block_header,
+                block_by_hash,
+                block_by_hash_path,
+                chain_tip,
                 full_blockchain,
                 new_transaction,
                 new_transaction_from_network,
+                signable_payload,
+                submit_transaction,
+                transaction_by_id,
+                transaction_proof,
+                address_proofs,
+                address_balance,
+                address_history,
+                first_address_history,
                 mine_block,
-                add_block
+                add_block,
+                wallet_addresses,
+                new_wallet_address
             ],
         )
-        .launch();
+}
+
+fn server_config(config: &NodeConfig) -> Result<Config, ConfigError> {
+    let environment = Environment::active().unwrap_or(Environment::Development);
+
+    Config::build(environment)
+        .address(config.address.clone())
+        .port(config.port)
+        .finalize()
+}
+
+// Reports a configuration the node can't start with, and exits. The config may be
+// what logging couldn't be set up from, so this doesn't go through the logger.
+fn exit_config<E: fmt::Display>(err: E) -> ! {
+    eprintln!("Couldn't start the node: {}", err);
+    exit(EX_CONFIG);
+}
+
+fn main() {
+    let mut config_path = String::new();
+    let mut phrase = String::new();
+
+    // Settings that override the ones of the config file
+    let mut seeds: Vec<String> = vec![];
+    let mut attempts: Option<u32> = None;
+    let mut snapshot: Option<String> = None;
+    let mut data_dir: Option<String> = None;
+    let mut address: Option<String> = None;
+    let mut port: Option<u16> = None;
+    let mut p2p_port: Option<u16> = None;
+    let mut spec_path: Option<String> = None;
+    let mut network: Option<String> = None;
+    let mut wallet_path: Option<String> = None;
+    let mut light = false;
+    let mut mine: Option<bool> = None;
+    let mut threads: Option<usize> = None;
+    let mut reward_addr: Option<String> = None;
+    let mut log_level: Option<String> = None;
+    let mut log_format: Option<String> = None;
+    let mut api_token: Option<String> = None;
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Tulipchain runner");
+        ap.refer(&mut config_path).add_option(
+            &["--config"],
+            Store,
+            "TOML config file, whose settings the other options override",
+        );
+        ap.refer(&mut seeds).add_option(
+            &["--connect"],
+            Collect,
+            "node address to connect to (base url), can be repeated",
+        );
+        ap.refer(&mut attempts).add_option(
+            &["--attempts"],
+            StoreOption,
+            "number of rounds over the seed nodes before giving up",
+        );
+        ap.refer(&mut snapshot).add_option(
+            &["--snapshot"],
+            StoreOption,
+            "chain snapshot to start from if the network can't be joined",
+        );
+        ap.refer(&mut data_dir).add_option(
+            &["--data-dir"],
+            StoreOption,
+            "directory the chain is persisted in",
+        );
+        ap.refer(&mut address).add_option(
+            &["--address"],
+            StoreOption,
+            "address the http api listens on",
+        );
+        ap.refer(&mut port)
+            .add_option(&["--port"], StoreOption, "port the http api listens on");
+        ap.refer(&mut spec_path).add_option(
+            &["--chain"],
+            StoreOption,
+            "chain spec file of the network to join (defaults to the dev network)",
+        );
+        ap.refer(&mut network).add_option(
+            &["--network"],
+            StoreOption,
+            "name of the network the chain spec has to be for",
+        );
+        ap.refer(&mut wallet_path).add_option(
+            &["--wallet"],
+            StoreOption,
+            "wallet file to load, or to create if it doesn't exist",
+        );
+        ap.refer(&mut phrase).add_option(
+            &["--recover"],
+            Store,
+            "seed phrase to restore the wallet from",
+        );
+        ap.refer(&mut light).add_option(
+            &["--light"],
+            StoreTrue,
+            "only follow the block headers, and check the wallet's transactions with proofs",
+        );
+        ap.refer(&mut p2p_port).add_option(
+            &["--p2p-port"],
+            StoreOption,
+            "port to accept binary protocol connections from peers on",
+        );
+        ap.refer(&mut mine)
+            .add_option(
+                &["--mine"],
+                StoreConst(Some(true)),
+                "mine blocks in the background",
+            )
+            .add_option(
+                &["--no-mine"],
+                StoreConst(Some(false)),
+                "only mine blocks when asked to through the api",
+            );
+        ap.refer(&mut threads).add_option(
+            &["--threads"],
+            StoreOption,
+            "number of threads to mine on",
+        );
+        ap.refer(&mut reward_addr).add_option(
+            &["--reward-addr"],
+            StoreOption,
+            "address to pay mining rewards to (defaults to the wallet's main address)",
+        );
+        ap.refer(&mut log_level).add_option(
+            &["--log-level"],
+            StoreOption,
+            "level to log at, optionally with levels per module, as in info,tulipchain::sync=debug",
+        );
+        ap.refer(&mut log_format)
+            .add_option(&["--log-format"], StoreOption, "either text or json");
+        ap.refer(&mut api_token).add_option(
+            &["--api-token"],
+            StoreOption,
+            "token that requests to the wallet and miner endpoints have to carry",
+        );
+        ap.parse_args_or_exit();
+    }
+
+    let mut config = if config_path.is_empty() {
+        NodeConfig::default()
+    } else {
+        NodeConfig::load(Path::new(&config_path)).unwrap_or_else(|err| exit_config(err))
+    };
+
+    if !seeds.is_empty() {
+        config.seeds = seeds;
+    }
+    config.attempts = attempts.unwrap_or(config.attempts);
+    config.snapshot = snapshot.map(PathBuf::from).or(config.snapshot);
+    config.data_dir = data_dir.map(PathBuf::from).or(config.data_dir);
+    config.address = address.unwrap_or(config.address);
+    config.port = port.unwrap_or(config.port);
+    config.p2p_port = p2p_port.or(config.p2p_port);
+    config.chain = spec_path.map(PathBuf::from).or(config.chain);
+    config.network = network.or(config.network);
+    config.wallet = wallet_path.map(PathBuf::from).or(config.wallet);
+    config.light = light || config.light;
+    config.mining.enabled = mine.unwrap_or(config.mining.enabled);
+    config.mining.threads = threads.unwrap_or(config.mining.threads);
+    config.mining.reward_addr = reward_addr.or(config.mining.reward_addr);
+    config.log_level = log_level.unwrap_or(config.log_level);
+    config.log_format = log_format.unwrap_or(config.log_format);
+    config.api.token = api_token.or(config.api.token);
+
+    if let Err(err) = config.validate() {
+        exit_config(err);
+    }
+
+    logging::init(config.log_filter().unwrap(), config.log_format().unwrap())
+        .unwrap_or_else(|err| exit_config(err));
+
+    let spec = match config.chain {
+        Some(ref path) => ChainSpec::load(path).unwrap_or_else(|err| exit_config(err)),
+        None => ChainSpec::dev(),
+    };
+
+    if let Err(err) = config.check_network(&spec) {
+        exit_config(err);
+    }
+
+    let mut wallet =
+        open_wallet(config.wallet.clone(), &phrase).unwrap_or_else(|err| exit_config(err));
+
+    let server = server_config(&config).unwrap_or_else(|err| exit_config(err));
+    // Rocket's own records go through the node's logger
+    let rocket = rocket::custom(server, false);
+    let auth = ApiAuth(config.api.token.clone());
+    if config.light {
+        launch_light(rocket, spec, config.seeds, wallet, auth);
+        return;
+    }
+
+    let server_config = ServerConfig {
+        address: config.address.clone(),
+        port: config.port,
+        p2p_port: config.p2p_port,
+    };
+
+    let options = BootstrapOptions {
+        seeds: config.seeds.clone(),
+        attempts: config.attempts,
+        snapshot: config.snapshot.clone(),
+        store: config.data_dir.clone().map(ChainStore::new),
+        spec,
+    };
+
+    // Exit with a code describing the failure, so that the node can be restarted
+    // (or not) accordingly
+    let mut chain = match Blockchain::init_chain(options, &server_config) {
+        Ok(chain) => chain,
+        Err(err) => {
+            error!("Couldn't start the node: {}", err);
+            exit(err.exit_code());
+        }
+    };
+    chain.p2p_port = config.p2p_port;
+    chain.reward_addr = config.reward_addr().unwrap();
+
+    // A restored wallet doesn't know which addresses it handed out before
+    if !phrase.is_empty() {
+        wallet.recover(|addr| chain.is_address_used(addr));
+        if let Err(err) = wallet.persist() {
+            error!("Couldn't save the wallet: {}", err);
+        }
+    }
+
+    // The wallet's main address receives the mining rewards, unless configured
+    // otherwise
+    chain.address = Some(wallet.address(0));
+    info!(
+        "Mining rewards are paid to {}",
+        address::encode(&chain.reward_addr())
+    );
+
+    let chain: SharedChain = Arc::new(RwLock::new(chain));
+    if let Some(p2p_port) = config.p2p_port {
+        match TcpListener::bind((config.address.as_str(), p2p_port)) {
+            Ok(listener) => wire::serve(listener, chain.clone()),
+            Err(err) => {
+                error!("Couldn't listen for peers on port {}: {}", p2p_port, err);
+                exit(1);
+            }
+        }
+    }
+
+    if config.mining.enabled {
+        info!("Mining on {} threads", config.mining.threads);
+        miner::start(chain.clone(), config.mining.threads);
+    }
+
+    full_node(rocket, chain, wallet, auth).launch();
 }
@@ -0,0 +1,133 @@
+use super::hex;
+use super::transaction::Transaction;
+use super::Digest;
+
+use sodiumoxide::crypto::hash::sha256;
+
+// Prefixes the preimage of inner nodes, so that an inner node can't be passed off
+// as a transaction id
+const NODE_PREFIX: u8 = 1;
+
+// Which side of the path a sibling sits on
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+// One step from a leaf towards the root
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ProofStep {
+    pub side: Side,
+    #[serde(with = "hex")]
+    pub sibling: Digest,
+}
+
+// Proof that a transaction is part of the block at the given height
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MerkleProof {
+    #[serde(with = "hex")]
+    pub txid: Digest,
+    pub height: usize,
+    #[serde(with = "hex")]
+    pub block_hash: Digest,
+    #[serde(with = "hex")]
+    pub merkle_root: Digest,
+    pub path: Vec<ProofStep>,
+}
+
+// A transaction along with the proof that it's part of a block
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ProvenTransaction {
+    pub transaction: Transaction,
+    pub proof: MerkleProof,
+}
+
+impl MerkleProof {
+    // Checks that the path leads from the transaction to the proof's root
+    pub fn verify(&self) -> bool {
+        verify(&self.txid, &self.path, &self.merkle_root)
+    }
+}
+
+fn hash_nodes(left: &[u8], right: &[u8]) -> Digest {
+    let mut preimage = Vec::with_capacity(1 + left.len() + right.len());
+    preimage.push(NODE_PREFIX);
+    preimage.extend_from_slice(left);
+    preimage.extend_from_slice(right);
+
+    let sha256::Digest(ref digest) = sha256::hash(&preimage);
+    digest.to_vec()
+}
+
+// Hashes a level of the tree into the one above it. A node without a sibling is
+// carried up as it is.
+fn next_level(level: &[Digest]) -> Vec<Digest> {
+    level
+        .chunks(2)
+        .map(|pair| {
+            if pair.len() == 2 {
+                hash_nodes(&pair[0], &pair[1])
+            } else {
+                pair[0].clone()
+            }
+        })
+        .collect()
+}
+
+// Computes the root of the tree over the given leaves. An empty tree has the digest
+// of nothing as its root.
+pub fn root(leaves: &[Digest]) -> Digest {
+    if leaves.is_empty() {
+        let sha256::Digest(ref digest) = sha256::hash(&[]);
+        return digest.to_vec();
+    }
+
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+
+    level.remove(0)
+}
+
+// Collects the siblings on the way from a leaf to the root
+pub fn prove(leaves: &[Digest], index: usize) -> Option<Vec<ProofStep>> {
+    if index >= leaves.len() {
+        return None;
+    }
+
+    let mut path = vec![];
+    let mut level = leaves.to_vec();
+    let mut index = index;
+
+    while level.len() > 1 {
+        let sibling = index ^ 1;
+        if sibling < level.len() {
+            path.push(ProofStep {
+                side: if sibling < index {
+                    Side::Left
+                } else {
+                    Side::Right
+                },
+                sibling: level[sibling].clone(),
+            });
+        }
+
+        level = next_level(&level);
+        index /= 2;
+    }
+
+    Some(path)
+}
+
+pub fn verify(leaf: &[u8], path: &[ProofStep], root: &[u8]) -> bool {
+    let computed = path
+        .iter()
+        .fold(leaf.to_vec(), |node, step| match step.side {
+            Side::Left => hash_nodes(&step.sibling, &node),
+            Side::Right => hash_nodes(&node, &step.sibling),
+        });
+
+    computed == root
+}
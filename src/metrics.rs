@@ -0,0 +1,288 @@
+// Counters describing what a node has been doing, exposed on /metrics in the
+// Prometheus text format
+
+use super::blockchain::Blockchain;
+
+use bincode::serialized_size;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Upper bounds of the buckets of reorganization depths, in blocks
+const DEPTH_BUCKETS: &[f64] = &[1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0];
+
+// Upper bounds of the buckets of request latencies, in seconds
+const LATENCY_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+// The request metrics as shared between the http handlers
+pub type SharedRequestMetrics = Arc<Mutex<RequestMetrics>>;
+
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    bounds: &'static [f64],
+
+    // Observations per bucket. Prometheus wants them cumulative, which they're only
+    // made when rendered.
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Histogram {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        if let Some(bucket) = self.bounds.iter().position(|&bound| value <= bound) {
+            self.counts[bucket] += 1;
+        }
+
+        self.sum += value;
+        self.count += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    // Writes the buckets, sum and count, each carrying the given labels
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}{}le=\"{}\"}} {}",
+                name, labels, separator, bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}{}le=\"+Inf\"}} {}",
+            name, labels, separator, self.count
+        );
+
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{}}}", labels)
+        };
+        let _ = writeln!(out, "{}_sum{} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, labels, self.count);
+    }
+}
+
+// What happened to the blocks and transactions a chain was handed, and how fast it
+// mined
+#[derive(Debug, Clone)]
+pub struct ChainMetrics {
+    pub blocks_accepted: u64,
+    pub blocks_rejected: BTreeMap<&'static str, u64>,
+    pub transactions_accepted: u64,
+    pub transactions_rejected: BTreeMap<&'static str, u64>,
+
+    // Number of blocks every reorganization replaced
+    pub reorg_depths: Histogram,
+
+    pub hashes: u64,
+
+    // Hashes per second of the last search for a nonce
+    pub hashrate: f64,
+}
+
+impl Default for ChainMetrics {
+    fn default() -> ChainMetrics {
+        ChainMetrics {
+            blocks_accepted: 0,
+            blocks_rejected: BTreeMap::new(),
+            transactions_accepted: 0,
+            transactions_rejected: BTreeMap::new(),
+            reorg_depths: Histogram::new(DEPTH_BUCKETS),
+            hashes: 0,
+            hashrate: 0.0,
+        }
+    }
+}
+
+impl ChainMetrics {
+    pub fn reject_block(&mut self, reason: &'static str) {
+        *self.blocks_rejected.entry(reason).or_insert(0) += 1;
+    }
+
+    pub fn reject_transaction(&mut self, reason: &'static str) {
+        *self.transactions_rejected.entry(reason).or_insert(0) += 1;
+    }
+
+    pub fn reorganized(&mut self, depth: usize) {
+        self.reorg_depths.observe(depth as f64);
+    }
+
+    pub fn searched(&mut self, hashes: u64, elapsed: Duration) {
+        self.hashes += hashes;
+
+        let elapsed = seconds(elapsed);
+        if elapsed > 0.0 {
+            self.hashrate = hashes as f64 / elapsed;
+        }
+    }
+}
+
+// How long the http handlers take, by route
+#[derive(Debug, Default)]
+pub struct RequestMetrics {
+    latencies: BTreeMap<String, Histogram>,
+}
+
+impl RequestMetrics {
+    pub fn observe(&mut self, handler: &str, elapsed: Duration) {
+        self.latencies
+            .entry(handler.to_string())
+            .or_insert_with(|| Histogram::new(LATENCY_BUCKETS))
+            .observe(seconds(elapsed));
+    }
+}
+
+// Renders the state of the chain along with the metrics in the Prometheus text format
+pub fn render(chain: &Blockchain, requests: &RequestMetrics) -> String {
+    let metrics = &chain.metrics;
+    let mut out = String::new();
+
+    let height = chain.chain.last().map_or(0, |tip| tip.header.ind);
+    let mempool_bytes: u64 = chain
+        .pending_transactions
+        .iter()
+        .map(|transaction| serialized_size(transaction).unwrap_or(0))
+        .sum();
+
+    gauge(
+        &mut out,
+        "tulipchain_chain_height",
+        "Height of the tip",
+        height as f64,
+    );
+    gauge(
+        &mut out,
+        "tulipchain_mempool_transactions",
+        "Transactions waiting to be mined",
+        chain.pending_transactions.len() as f64,
+    );
+    gauge(
+        &mut out,
+        "tulipchain_mempool_bytes",
+        "Encoded size of the transactions waiting to be mined",
+        mempool_bytes as f64,
+    );
+    gauge(
+        &mut out,
+        "tulipchain_peers",
+        "Known peers",
+        chain.peers.len() as f64,
+    );
+
+    counter(
+        &mut out,
+        "tulipchain_blocks_accepted_total",
+        "Blocks mined or received that extended the tip",
+        metrics.blocks_accepted,
+    );
+    counter_by_reason(
+        &mut out,
+        "tulipchain_blocks_rejected_total",
+        "Blocks turned down, by reason",
+        &metrics.blocks_rejected,
+    );
+    counter(
+        &mut out,
+        "tulipchain_transactions_accepted_total",
+        "Transactions added to the mempool",
+        metrics.transactions_accepted,
+    );
+    counter_by_reason(
+        &mut out,
+        "tulipchain_transactions_rejected_total",
+        "Transactions turned down, by reason",
+        &metrics.transactions_rejected,
+    );
+
+    let name = "tulipchain_reorg_depth_blocks";
+    header(
+        &mut out,
+        name,
+        "Blocks replaced by each reorganization",
+        "histogram",
+    );
+    metrics.reorg_depths.render(&mut out, name, "");
+
+    counter(
+        &mut out,
+        "tulipchain_mining_hashes_total",
+        "Hashes tried while searching for nonces",
+        metrics.hashes,
+    );
+    gauge(
+        &mut out,
+        "tulipchain_mining_hashrate",
+        "Hashes per second of the last search for a nonce",
+        metrics.hashrate,
+    );
+
+    let name = "tulipchain_http_request_duration_seconds";
+    header(
+        &mut out,
+        name,
+        "Time taken by the http handlers",
+        "histogram",
+    );
+    for (handler, latency) in &requests.latencies {
+        latency.render(&mut out, name, &format!("handler=\"{}\"", escape(handler)));
+    }
+
+    out
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: f64) {
+    header(out, name, help, "gauge");
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, help, "counter");
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn counter_by_reason(out: &mut String, name: &str, help: &str, counts: &BTreeMap<&str, u64>) {
+    header(out, name, help, "counter");
+    for (reason, count) in counts {
+        let _ = writeln!(out, "{}{{reason=\"{}\"}} {}", name, escape(reason), count);
+    }
+}
+
+// Escapes a label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9
+}
@@ -0,0 +1,117 @@
+use super::blockchain::{Block, Nonce, SharedChain};
+use super::PublicKey;
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+// Number of nonces a thread tries between two checks of whether the tip moved on
+const TIP_CHECK_INTERVAL: u64 = 10_000;
+
+// How the node goes about mining blocks
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MiningState {
+    // A block is mined whenever one is requested through the api
+    OnDemand,
+    // Blocks are mined continuously, on the given number of threads
+    Background { threads: usize },
+}
+
+impl Default for MiningState {
+    fn default() -> MiningState {
+        MiningState::OnDemand
+    }
+}
+
+// Mines blocks on top of the tip in the background, on `threads` threads
+pub fn start(chain: SharedChain, threads: usize) {
+    chain.write().unwrap().mining = MiningState::Background { threads };
+
+    thread::spawn(move || loop {
+        let (block, difficulty) = {
+            let reader = chain.read().unwrap();
+            (
+                reader.next_block(reader.reward_addr()),
+                reader.spec.difficulty,
+            )
+        };
+
+        let started = Instant::now();
+        let (nonce, hashes) = search(&chain, &block, difficulty, threads);
+
+        let mut writer = chain.write().unwrap();
+        writer.metrics.searched(hashes, started.elapsed());
+
+        let mut block = block;
+        block.header.nonce = match nonce {
+            Some(nonce) => nonce,
+            None => continue,
+        };
+
+        // The block was mined on the tip, unless a peer's block came in at the last
+        // moment, in which case it's turned down
+        if writer.accept_block(block.clone()).is_ok() {
+            writer.broadcast_block(block);
+        }
+    });
+}
+
+// Searches for a nonce with which the block meets the difficulty. Each thread tries
+// every threads-th nonce. Gives up when the tip moves on, since the block would no
+// longer extend it, or when no nonce works. Also returns the number of hashes tried.
+fn search(
+    chain: &SharedChain,
+    block: &Block,
+    difficulty: usize,
+    threads: usize,
+) -> (Option<Nonce>, u64) {
+    let found: Arc<Mutex<Option<Nonce>>> = Arc::new(Mutex::new(None));
+    let done = Arc::new(AtomicBool::new(false));
+    let hashes = Arc::new(AtomicUsize::new(0));
+
+    let handles: Vec<_> = (0..threads)
+        .map(|offset| {
+            let chain = chain.clone();
+            let mut header = block.header.clone();
+            let found = found.clone();
+            let done = done.clone();
+            let hashes = hashes.clone();
+
+            thread::spawn(move || {
+                header.nonce = offset as Nonce;
+                let mut tries: u64 = 0;
+
+                while !done.load(Ordering::Relaxed) {
+                    tries += 1;
+                    if header.meets_difficulty(difficulty) {
+                        *found.lock().unwrap() = Some(header.nonce);
+                        done.store(true, Ordering::Relaxed);
+                        break;
+                    }
+
+                    header.nonce = match header.nonce.checked_add(threads as Nonce) {
+                        Some(nonce) => nonce,
+                        None => break,
+                    };
+
+                    if tries % TIP_CHECK_INTERVAL == 0
+                        && chain.read().unwrap().chain.len() != header.ind
+                    {
+                        done.store(true, Ordering::Relaxed);
+                    }
+                }
+
+                hashes.fetch_add(tries as usize, Ordering::Relaxed);
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let nonce = *found.lock().unwrap();
+    (nonce, hashes.load(Ordering::Relaxed) as u64)
+}
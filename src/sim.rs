@@ -0,0 +1,622 @@
+// Discrete-event simulation of a network of miners that follow the consensus rules of
+// `Blockchain`. Time is virtual, and message delays and losses as well as which miner
+// finds the next block are drawn from a generator seeded by the config, so a run is
+// reproduced exactly by its seed, down to the block hashes. It's meant for studying
+// forks, orphans and attacks on the consensus rules, rather than for running nodes.
+
+use super::blockchain::{Block, BlockHeader, Blockchain};
+use super::clock::{ManualClock, SharedClock};
+use super::keys::SeededKeys;
+use super::spec::ChainSpec;
+use super::sync::{self, Branch};
+use super::{Digest, PublicKey};
+
+use chrono::Duration;
+use rand::{Rng, SeedableRng, XorShiftRng};
+use sodiumoxide::crypto::sign;
+use std::cmp::{self, Ordering};
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::Arc;
+
+// Virtual time, in milliseconds since the genesis block
+pub type Millis = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    // Mines on the longest chain it knows of, and announces its blocks right away
+    Honest,
+    // Withholds its blocks and reveals them when that orphans the other miners'
+    // blocks, as in Eyal and Sirer's selfish mining
+    Selfish,
+}
+
+#[derive(Debug, Clone)]
+pub struct Miner {
+    // Share of the network's hash power, from 0 to 1. Nodes without any only relay.
+    pub power: f64,
+    pub strategy: Strategy,
+}
+
+impl Miner {
+    pub fn honest(power: f64) -> Miner {
+        Miner {
+            power,
+            strategy: Strategy::Honest,
+        }
+    }
+
+    pub fn selfish(power: f64) -> Miner {
+        Miner {
+            power,
+            strategy: Strategy::Selfish,
+        }
+    }
+}
+
+// Messages between the nodes on the side and the other nodes are lost in the window
+#[derive(Debug, Clone)]
+pub struct Partition {
+    pub from: Millis,
+    pub until: Millis,
+    pub side: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    pub seed: u64,
+
+    // One node per miner, every node connected to every other one
+    pub miners: Vec<Miner>,
+
+    // Average time between two blocks of the whole network
+    pub block_interval: Millis,
+
+    // Messages take the latency plus up to the jitter to arrive, and get lost at the
+    // drop rate
+    pub latency: Millis,
+    pub jitter: Millis,
+    pub drop_rate: f64,
+
+    pub partitions: Vec<Partition>,
+
+    // Nonces are searched for for real, so the difficulty is kept low
+    pub difficulty: usize,
+}
+
+impl SimConfig {
+    pub fn new(seed: u64, miners: Vec<Miner>) -> SimConfig {
+        SimConfig {
+            seed,
+            miners,
+            block_interval: 600_000,
+            latency: 100,
+            jitter: 100,
+            drop_rate: 0.0,
+            partitions: vec![],
+            difficulty: 1,
+        }
+    }
+
+    fn partitioned(&self, from: usize, to: usize, at: Millis) -> bool {
+        self.partitions.iter().any(|partition| {
+            partition.from <= at
+                && at < partition.until
+                && partition.side.contains(&from) != partition.side.contains(&to)
+        })
+    }
+}
+
+// Outcome of a run
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimReport {
+    // Blocks found by each miner
+    pub mined: Vec<usize>,
+
+    // Blocks of each miner in the longest chain of the honest nodes
+    pub main_chain: Vec<usize>,
+
+    pub height: usize,
+    pub tip: Digest,
+
+    // Reorganizations across all nodes, and the most blocks one of them replaced
+    pub reorgs: u64,
+    pub deepest_reorg: usize,
+}
+
+impl SimReport {
+    // Share of the mined blocks that didn't make it into the main chain
+    pub fn orphan_rate(&self) -> f64 {
+        let mined: usize = self.mined.iter().sum();
+        if mined == 0 {
+            return 0.0;
+        }
+
+        1.0 - self.height as f64 / mined as f64
+    }
+
+    // Share of the main chain's blocks mined by the miner, which is its share of the
+    // rewards
+    pub fn share(&self, miner: usize) -> f64 {
+        if self.height == 0 {
+            return 0.0;
+        }
+
+        self.main_chain[miner] as f64 / self.height as f64
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Message {
+    Block(Block),
+    // Asks for a block whose children arrived before it did
+    GetBlock(Digest),
+}
+
+#[derive(Debug)]
+enum Event {
+    // The miner finds a block on top of its current tip
+    Found(usize),
+    Deliver {
+        from: usize,
+        to: usize,
+        message: Message,
+    },
+}
+
+// An event in the queue. Events at the same time happen in the order they were
+// scheduled in, which keeps runs reproducible.
+#[derive(Debug)]
+struct Scheduled {
+    time: Millis,
+    seq: u64,
+    event: Event,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Scheduled) -> bool {
+        (self.time, self.seq) == (other.time, other.seq)
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Scheduled) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Reversed, since the queue pops the greatest event and the earliest should go first
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Scheduled) -> Ordering {
+        (other.time, other.seq).cmp(&(self.time, self.seq))
+    }
+}
+
+struct Node {
+    chain: Blockchain,
+    miner: Miner,
+    reward_addr: PublicKey,
+
+    // Every valid block the node has, by hash, including those off its chain
+    blocks: HashMap<Digest, Block>,
+
+    // Blocks that arrived before their parent, by the parent's hash
+    orphans: HashMap<Digest, Vec<Block>>,
+
+    // Selfish miners keep track of the height up to which they published their own
+    // blocks, of the best chain everyone knows of, and of whether they're racing the
+    // other miners with a block of the same height
+    published: usize,
+    public_height: usize,
+    public_tip: Digest,
+    racing: bool,
+}
+
+impl Node {
+    // Switches over to the chain ending in the given block if it's longer than our own.
+    // The block goes through the same fork choice as one a node receives from a peer,
+    // except that the branch leading to it is put together from the blocks we have
+    // rather than downloaded. Returns the number of blocks replaced if it did.
+    fn adopt(&mut self, tip_hash: &Digest, difficulty: usize) -> Option<usize> {
+        let tip = self.blocks[tip_hash].clone();
+        match self.chain.accept_block(tip) {
+            Ok(()) => return Some(0),
+            Err(ref err) if err.is_ahead() => {}
+            Err(_) => return None,
+        }
+
+        // Walk back to where the chain forks off our own
+        let mut blocks = vec![];
+        let mut hash = tip_hash.clone();
+        while !self.is_on_chain(&hash) {
+            let block = self.blocks[&hash].clone();
+            hash = block.header.previous_hash.clone().unwrap();
+            blocks.push(block);
+        }
+        blocks.reverse();
+
+        let fork_height = blocks[0].header.ind;
+        let anchor = self.chain.chain[fork_height - 1].header.clone();
+        let headers: Vec<BlockHeader> = blocks.iter().map(|block| block.header.clone()).collect();
+        if Blockchain::validate_header_chain(Some(&anchor), &headers, difficulty).is_err() {
+            return None;
+        }
+
+        let depth = self.chain.chain.len() - fork_height;
+        let branch = Branch {
+            fork_height,
+            blocks,
+        };
+        match sync::apply_branch(&mut self.chain, branch) {
+            Ok(added) if added > 0 => Some(depth),
+            _ => None,
+        }
+    }
+
+    fn is_on_chain(&self, hash: &Digest) -> bool {
+        let height = self.blocks[hash].header.ind;
+
+        self.chain.chain.get(height).map(|block| block.hash()) == Some(hash.clone())
+    }
+}
+
+pub struct Simulation {
+    config: SimConfig,
+    spec: ChainSpec,
+    rng: XorShiftRng,
+    now: Millis,
+
+    // The chains' clock, which is kept at the virtual time
+    clock: Arc<ManualClock>,
+
+    queue: BinaryHeap<Scheduled>,
+    seq: u64,
+    nodes: Vec<Node>,
+
+    // When the last message on each link arrives. Links deliver in order, so that
+    // blocks announced one after the other arrive after their parents.
+    links: HashMap<(usize, usize), Millis>,
+
+    mined: Vec<usize>,
+    deepest_reorg: usize,
+}
+
+impl Simulation {
+    pub fn new(config: SimConfig) -> Simulation {
+        let seed = config.seed;
+        let mut rng =
+            XorShiftRng::from_seed([0x9e37_79b9, (seed >> 32) as u32, seed as u32, 0x7f4a_7c15]);
+
+        let mut spec = ChainSpec::dev();
+        spec.difficulty = config.difficulty;
+        let clock = Arc::new(ManualClock::new(spec.genesis.timestamp));
+
+        let nodes = config
+            .miners
+            .iter()
+            .map(|miner| {
+                let mut seed = [0; sign::SEEDBYTES];
+                rng.fill_bytes(&mut seed);
+                let chain = Blockchain::with_sources(
+                    spec.clone(),
+                    SharedClock::new(clock.clone()),
+                    &mut SeededKeys::new(&seed),
+                );
+                let reward_addr = chain.address.as_ref().unwrap().public_key;
+                let genesis = chain.chain[0].clone();
+
+                let mut blocks = HashMap::new();
+                blocks.insert(genesis.hash(), genesis.clone());
+
+                Node {
+                    chain,
+                    miner: miner.clone(),
+                    reward_addr,
+                    blocks,
+                    orphans: HashMap::new(),
+                    published: 0,
+                    public_height: 0,
+                    public_tip: genesis.hash(),
+                    racing: false,
+                }
+            })
+            .collect();
+
+        let mut simulation = Simulation {
+            mined: vec![0; config.miners.len()],
+            config,
+            spec,
+            rng,
+            now: 0,
+            clock,
+            queue: BinaryHeap::new(),
+            seq: 0,
+            nodes,
+            links: HashMap::new(),
+            deepest_reorg: 0,
+        };
+
+        for miner in 0..simulation.nodes.len() {
+            simulation.schedule_mining(miner);
+        }
+
+        simulation
+    }
+
+    pub fn now(&self) -> Millis {
+        self.now
+    }
+
+    // The chain a node is on. Selfish miners' chains include their withheld blocks.
+    pub fn chain(&self, node: usize) -> &Blockchain {
+        &self.nodes[node].chain
+    }
+
+    // Runs the simulation until the given time, and reports on it
+    pub fn run(&mut self, until: Millis) -> SimReport {
+        loop {
+            match self.queue.peek() {
+                Some(next) if next.time <= until => {}
+                _ => break,
+            }
+
+            let next = self.queue.pop().unwrap();
+            self.now = next.time;
+            self.handle(next.event);
+        }
+
+        self.now = until;
+        self.report()
+    }
+
+    pub fn report(&self) -> SimReport {
+        // The main chain is the longest one of the honest nodes, or the first node's if
+        // there are none
+        let main = self
+            .nodes
+            .iter()
+            .filter(|node| node.miner.strategy == Strategy::Honest)
+            .map(|node| &node.chain)
+            .max_by_key(|chain| chain.chain.len())
+            .unwrap_or(&self.nodes[0].chain);
+
+        let miners: HashMap<PublicKey, usize> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.reward_addr, i))
+            .collect();
+
+        let mut main_chain = vec![0; self.nodes.len()];
+        for block in main.chain.iter().skip(1) {
+            if let Some(&miner) = miners.get(&block.body.coinbase_transaction.recipient_addr) {
+                main_chain[miner] += 1;
+            }
+        }
+
+        let tip = main.chain.last().unwrap();
+
+        SimReport {
+            mined: self.mined.clone(),
+            main_chain,
+            height: tip.header.ind,
+            tip: tip.hash(),
+            reorgs: self
+                .nodes
+                .iter()
+                .map(|node| node.chain.metrics.reorg_depths.count())
+                .sum(),
+            deepest_reorg: self.deepest_reorg,
+        }
+    }
+
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::Found(miner) => self.found(miner),
+            Event::Deliver {
+                from,
+                to,
+                message: Message::Block(block),
+            } => self.receive_block(to, from, block),
+            Event::Deliver {
+                from,
+                to,
+                message: Message::GetBlock(hash),
+            } => {
+                if let Some(block) = self.nodes[to].blocks.get(&hash).cloned() {
+                    self.send(to, from, Message::Block(block));
+                }
+            }
+        }
+    }
+
+    fn schedule(&mut self, time: Millis, event: Event) {
+        self.seq += 1;
+        self.queue.push(Scheduled {
+            time,
+            seq: self.seq,
+            event,
+        });
+    }
+
+    // Blocks are found at random, at a rate in proportion to the miner's power
+    fn schedule_mining(&mut self, miner: usize) {
+        let power = self.nodes[miner].miner.power;
+        if power <= 0.0 {
+            return;
+        }
+
+        let mean = self.config.block_interval as f64 / power;
+        let uniform: f64 = 1.0 - self.rng.gen::<f64>();
+        let delay = (-uniform.ln() * mean) as Millis;
+
+        let time = self.now + delay;
+        self.schedule(time, Event::Found(miner));
+    }
+
+    fn found(&mut self, miner: usize) {
+        self.clock
+            .set(self.spec.genesis.timestamp + Duration::milliseconds(self.now as i64));
+
+        let block = {
+            let node = &self.nodes[miner];
+            let mut block = node.chain.next_block(node.reward_addr);
+            block.header.nonce = Blockchain::find_nonce(&block.header, self.spec.difficulty);
+            block
+        };
+
+        self.mined[miner] += 1;
+        {
+            let node = &mut self.nodes[miner];
+            node.blocks.insert(block.hash(), block.clone());
+            node.chain
+                .accept_block(block.clone())
+                .expect("a miner's block extends its own tip");
+        }
+
+        match self.nodes[miner].miner.strategy {
+            Strategy::Honest => self.relay(miner, block),
+            Strategy::Selfish => {
+                // Having been even with the others, the lead now wins the race
+                if self.nodes[miner].racing {
+                    self.nodes[miner].racing = false;
+                    self.publish(miner, block.header.ind);
+                }
+            }
+        }
+
+        self.schedule_mining(miner);
+    }
+
+    fn receive_block(&mut self, node: usize, from: usize, block: Block) {
+        let hash = block.hash();
+        if self.nodes[node].blocks.contains_key(&hash) {
+            return;
+        }
+
+        if !block.has_valid_merkle_root() || !block.header.meets_difficulty(self.spec.difficulty) {
+            return;
+        }
+
+        let parent = match block.header.previous_hash {
+            Some(ref parent) => parent.clone(),
+            None => return,
+        };
+
+        // Ask the peer for the parent, and come back to the block once it's there
+        if !self.nodes[node].blocks.contains_key(&parent) {
+            self.nodes[node]
+                .orphans
+                .entry(parent.clone())
+                .or_insert_with(Vec::new)
+                .push(block);
+            self.send(node, from, Message::GetBlock(parent));
+            return;
+        }
+
+        self.nodes[node].blocks.insert(hash.clone(), block.clone());
+
+        match self.nodes[node].miner.strategy {
+            Strategy::Honest => {
+                if self.adopt(node, &hash) {
+                    self.relay(node, block);
+                }
+            }
+            Strategy::Selfish => self.react(node, &block),
+        }
+
+        if let Some(children) = self.nodes[node].orphans.remove(&hash) {
+            for child in children {
+                self.receive_block(node, from, child);
+            }
+        }
+    }
+
+    // Switches the node over to the chain ending in the given block if it's longer than
+    // its own. Returns whether it did.
+    fn adopt(&mut self, node: usize, tip_hash: &Digest) -> bool {
+        match self.nodes[node].adopt(tip_hash, self.spec.difficulty) {
+            Some(depth) => {
+                self.deepest_reorg = cmp::max(self.deepest_reorg, depth);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // How a selfish miner responds to a block of the other miners
+    fn react(&mut self, node: usize, block: &Block) {
+        let height = block.header.ind;
+        if height <= self.nodes[node].public_height {
+            return;
+        }
+
+        self.nodes[node].public_height = height;
+        self.nodes[node].public_tip = block.hash();
+
+        let private_height = self.nodes[node].chain.chain.len() - 1;
+        if private_height < height {
+            // The others are ahead, so start over from their chain
+            let public_tip = self.nodes[node].public_tip.clone();
+            self.adopt(node, &public_tip);
+            self.nodes[node].published = height;
+            self.nodes[node].racing = false;
+        } else if private_height == height {
+            // Even again, so race the others with the withheld block
+            self.nodes[node].racing = true;
+            self.publish(node, height);
+        } else if private_height == height + 1 {
+            // Only one block ahead, which is too close to hold on to
+            self.nodes[node].racing = false;
+            self.publish(node, private_height);
+        } else {
+            // Still comfortably ahead, so only match the others' height
+            self.publish(node, height);
+        }
+    }
+
+    // Announces a selfish miner's withheld blocks up to the given height
+    fn publish(&mut self, node: usize, height: usize) {
+        let from = self.nodes[node].published + 1;
+        let blocks: Vec<Block> = self.nodes[node]
+            .chain
+            .blocks(from, (height + 1).saturating_sub(from));
+
+        for block in blocks {
+            self.relay(node, block);
+        }
+
+        let node = &mut self.nodes[node];
+        node.published = cmp::max(node.published, height);
+        if height > node.public_height {
+            node.public_height = height;
+            node.public_tip = node.chain.chain[height].hash();
+        }
+    }
+
+    fn relay(&mut self, from: usize, block: Block) {
+        for to in 0..self.nodes.len() {
+            if to != from {
+                self.send(from, to, Message::Block(block.clone()));
+            }
+        }
+    }
+
+    fn send(&mut self, from: usize, to: usize, message: Message) {
+        if self.config.partitioned(from, to, self.now) {
+            return;
+        }
+
+        if self.config.drop_rate > 0.0 && self.rng.gen::<f64>() < self.config.drop_rate {
+            return;
+        }
+
+        let delay = self.config.latency + self.rng.gen_range(0, self.config.jitter + 1);
+        let last = self.links.get(&(from, to)).cloned().unwrap_or(0);
+        let time = cmp::max(self.now + delay, last);
+        self.links.insert((from, to), time);
+
+        self.schedule(time, Event::Deliver { from, to, message });
+    }
+}
@@ -0,0 +1,154 @@
+use super::blockchain::{Block, Blockchain, Nonce};
+use super::address;
+use super::wire::Magic;
+use super::{PublicKey, Tulips};
+
+use chrono::prelude::*;
+use serde_json;
+use sodiumoxide::crypto::sign::ed25519;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::iter;
+use std::path::Path;
+
+// A proof of work digest can't have more leading zero bytes than it has bytes
+const MAX_DIFFICULTY: usize = 32;
+
+// Nonce of the development network's genesis block
+const DEV_GENESIS_NONCE: Nonce = 16689;
+
+// Describes a network: every node on the network has to be started with the same
+// spec, so that they agree on the genesis block and the consensus parameters
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ChainSpec {
+    // Human readable name, e.g. "testnet"
+    pub network: String,
+
+    // Prefixes every message of the binary protocol, so that nodes of different
+    // networks can't talk to each other
+    pub magic: Magic,
+
+    // Number of leading zero bytes a proof of work digest needs
+    pub difficulty: usize,
+
+    // Tulips paid out to the miner of each block
+    pub reward: Tulips,
+
+    pub genesis: GenesisSpec,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct GenesisSpec {
+    pub timestamp: DateTime<Utc>,
+
+    // Recipient of the genesis block's reward
+    #[serde(with = "address")]
+    pub coinbase_addr: PublicKey,
+    pub nonce: Nonce,
+
+    // Tulips handed out in the genesis block
+    pub premine: Vec<Allocation>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Allocation {
+    #[serde(with = "address")]
+    pub addr: PublicKey,
+    pub value: Tulips,
+}
+
+#[derive(Debug)]
+pub enum SpecError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+    // The difficulty can't be met by any digest
+    Difficulty(usize),
+    // The genesis nonce doesn't satisfy the spec's difficulty
+    GenesisNonce,
+    // Two of the genesis block's transactions are the same, so they'd share an id
+    DuplicateAllocation,
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SpecError::Io(ref err) => write!(f, "couldn't read the chain spec: {}", err),
+            SpecError::Parse(ref err) => write!(f, "couldn't parse the chain spec: {}", err),
+            SpecError::Difficulty(difficulty) => {
+                write!(f, "difficulty {} is above {}", difficulty, MAX_DIFFICULTY)
+            }
+            SpecError::GenesisNonce => write!(f, "the genesis nonce isn't valid"),
+            SpecError::DuplicateAllocation => {
+                write!(f, "the genesis block pays the same allocation twice")
+            }
+        }
+    }
+}
+
+impl ChainSpec {
+    // Spec of the local development network, used when no spec file is given
+    pub fn dev() -> ChainSpec {
+        ChainSpec {
+            network: String::from("dev"),
+            magic: *b"TLIP",
+            difficulty: 2,
+            reward: 5,
+            genesis: GenesisSpec {
+                timestamp: Utc.ymd(2018, 5, 22).and_hms(0, 0, 0),
+                // Nobody holds the key to the genesis reward
+                coinbase_addr: ed25519::PublicKey([0; ed25519::PUBLICKEYBYTES]),
+                nonce: DEV_GENESIS_NONCE,
+                premine: vec![],
+            },
+        }
+    }
+
+    // Reads a spec file in JSON format, and checks that it describes a usable network
+    pub fn load(path: &Path) -> Result<ChainSpec, SpecError> {
+        let file = File::open(path).map_err(SpecError::Io)?;
+        let spec: ChainSpec = serde_json::from_reader(file).map_err(SpecError::Parse)?;
+
+        spec.validate()?;
+
+        Ok(spec)
+    }
+
+    pub fn validate(&self) -> Result<(), SpecError> {
+        if self.difficulty > MAX_DIFFICULTY {
+            return Err(SpecError::Difficulty(self.difficulty));
+        }
+
+        let genesis = self.genesis_block();
+        if !genesis.header.meets_difficulty(self.difficulty) {
+            return Err(SpecError::GenesisNonce);
+        }
+
+        let mut txids = HashSet::new();
+        let unique = iter::once(&genesis.body.coinbase_transaction)
+            .chain(&genesis.body.transactions)
+            .all(|transaction| txids.insert(transaction.id()));
+        if !unique {
+            return Err(SpecError::DuplicateAllocation);
+        }
+
+        Ok(())
+    }
+
+    pub fn genesis_block(&self) -> Block {
+        Blockchain::from_blocks(self.clone(), vec![]).create_genesis_block()
+    }
+
+    // Finds a genesis nonce that satisfies the difficulty, e.g. when setting up a new
+    // network or after changing the genesis block
+    pub fn mine_genesis(&mut self) {
+        self.genesis.nonce = Blockchain::find_nonce(&self.genesis_block().header, self.difficulty);
+    }
+}
+
+impl Default for ChainSpec {
+    fn default() -> ChainSpec {
+        ChainSpec::dev()
+    }
+}
@@ -0,0 +1,99 @@
+use super::blockchain::Blockchain;
+use super::index::Balance;
+use super::miner::MiningState;
+use super::{address, hex};
+use super::{Digest, PublicKey};
+
+// What the index page tells about the node
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct NodeStatus {
+    pub version: String,
+    pub network: String,
+    pub height: usize,
+    #[serde(with = "hex")]
+    pub tip: Digest,
+    pub peers: usize,
+
+    // Number of transactions waiting to be mined
+    pub mempool: usize,
+    pub mining: MiningState,
+    pub difficulty: usize,
+
+    // Address the node's mining rewards are paid to
+    #[serde(with = "address")]
+    pub address: PublicKey,
+    pub balance: Balance,
+}
+
+impl NodeStatus {
+    pub fn of(chain: &Blockchain) -> NodeStatus {
+        let tip = chain.chain.last().unwrap();
+        let address = chain.reward_addr();
+
+        NodeStatus {
+            version: String::from(env!("CARGO_PKG_VERSION")),
+            network: chain.spec.network.clone(),
+            height: tip.header.ind,
+            tip: tip.hash(),
+            peers: chain.peers.len(),
+            mempool: chain.pending_transactions.len(),
+            mining: chain.mining,
+            difficulty: chain.spec.difficulty,
+            balance: chain.balance(&address),
+            address,
+        }
+    }
+
+    // Renders the status as a page for browsers
+    pub fn to_html(&self) -> String {
+        let mining = match self.mining {
+            MiningState::OnDemand => String::from("on demand"),
+            MiningState::Background { threads } => format!("on {} threads", threads),
+        };
+
+        let rows = vec![
+            ("Version", self.version.clone()),
+            ("Network", self.network.clone()),
+            ("Height", self.height.to_string()),
+            ("Tip", hex::encode(&self.tip)),
+            ("Peers", self.peers.to_string()),
+            ("Pending transactions", self.mempool.to_string()),
+            ("Mining", mining),
+            ("Difficulty", self.difficulty.to_string()),
+            ("Address", address::encode(&self.address)),
+            ("Confirmed balance", self.balance.confirmed.to_string()),
+            ("Pending balance", self.balance.pending.to_string()),
+        ];
+
+        let mut html = String::from(
+            "<!DOCTYPE html>\n<html>\n<head><title>Tulip node</title></head>\n<body>\n\
+             <h1>Tulip node</h1>\n<table>\n",
+        );
+        for (name, value) in rows {
+            html.push_str(&format!(
+                "<tr><th>{}</th><td>{}</td></tr>\n",
+                name,
+                escape(&value)
+            ));
+        }
+        html.push_str("</table>\n</body>\n</html>\n");
+
+        html
+    }
+}
+
+// The network's name comes from the spec file, so it isn't trusted to be plain text
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
@@ -0,0 +1,102 @@
+use super::blockchain::Block;
+
+use serde_json;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+const SNAPSHOT_FILE: &str = "chain.json";
+const SNAPSHOT_TMP_FILE: &str = "chain.json.tmp";
+
+// Blocks added since the snapshot was written, one JSON encoded block per line
+const LOG_FILE: &str = "blocks.log";
+
+// Persists the chain in a data directory so that a restarted node can resume
+// synchronizing from its last known tip. The chain is written as a whole when it's
+// reorganized, and blocks added on top of it are appended to a log, so that adding a
+// block doesn't take longer the longer the chain gets.
+#[derive(Debug, Clone)]
+pub struct ChainStore {
+    dir: PathBuf,
+}
+
+impl ChainStore {
+    pub fn new(dir: PathBuf) -> ChainStore {
+        ChainStore { dir }
+    }
+
+    // Loads the persisted chain, if there is one
+    pub fn load(&self) -> io::Result<Option<Vec<Block>>> {
+        let path = self.dir.join(SNAPSHOT_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let mut blocks = read_snapshot(&path)?;
+        let log = match File::open(self.dir.join(LOG_FILE)) {
+            Ok(log) => log,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Some(blocks)),
+            Err(err) => return Err(err),
+        };
+
+        for line in BufReader::new(log).lines() {
+            // A crash while a block was appended leaves the last line incomplete
+            let block: Block = match serde_json::from_str(&line?) {
+                Ok(block) => block,
+                Err(_) => break,
+            };
+
+            // The snapshot may already hold the block, if the node stopped right after
+            // writing it
+            let height = block.header.ind;
+            if height > blocks.len() {
+                break;
+            }
+
+            blocks.truncate(height);
+            blocks.push(block);
+        }
+
+        Ok(Some(blocks))
+    }
+
+    // Overwrites the persisted chain. The snapshot is written to a temporary file
+    // first, so a crash mid-write leaves the previous snapshot intact.
+    pub fn save(&self, chain: &[Block]) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+
+        let tmp_path = self.dir.join(SNAPSHOT_TMP_FILE);
+        {
+            let file = File::create(&tmp_path)?;
+            serde_json::to_writer(file, chain)
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        }
+
+        fs::rename(tmp_path, self.dir.join(SNAPSHOT_FILE))?;
+
+        // The snapshot holds the logged blocks now
+        match fs::remove_file(self.dir.join(LOG_FILE)) {
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    // Adds a block on top of the persisted chain
+    pub fn append(&self, block: &Block) -> io::Result<()> {
+        let mut line =
+            serde_json::to_vec(block).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        line.push(b'\n');
+
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(LOG_FILE))?;
+        log.write_all(&line)
+    }
+}
+
+// Reads a chain snapshot, e.g. one copied from another node's data directory
+pub fn read_snapshot(path: &Path) -> io::Result<Vec<Block>> {
+    let file = File::open(path)?;
+    serde_json::from_reader(file).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
@@ -0,0 +1,529 @@
+use super::blockchain::{Block, BlockError, BlockHeader, Blockchain, NodeAddr, SharedChain};
+use super::spec::ChainSpec;
+use super::transaction::{Transaction, TxError};
+use super::wire::{self, Magic, Message};
+use super::{Digest, Tulips};
+
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::thread;
+use reqwest;
+
+// Maximum number of headers served for a single request
+pub const MAX_HEADERS: usize = 2000;
+
+// Maximum number of block bodies served for a single request
+pub const MAX_BLOCKS: usize = 100;
+
+// Most headers downloaded from a peer in one sync, so that a peer can't keep us
+// downloading pages of headers forever. A longer chain is caught up with over the
+// following syncs.
+pub const MAX_SYNC_HEADERS: usize = 500 * MAX_HEADERS;
+
+#[derive(Debug)]
+pub enum SyncError {
+    // A peer couldn't be reached, or answered with something unexpected
+    Request(String),
+    // The headers served by a peer don't link up or lack proof of work
+    InvalidHeaders(BlockError),
+    // The peer's chain starts at a different genesis block
+    GenesisMismatch,
+    // None of the peers could serve the bodies starting at the given height
+    MissingBodies(usize),
+    // The bodies served for a height don't hash to the header they were requested
+    // for, by any of the peers
+    BodyMismatch(usize),
+    // A body's merkle root doesn't match its transactions, one of them is invalid, or
+    // they don't add up
+    InvalidBlock(BlockError),
+    // A peer served a transaction with a proof that doesn't check out
+    InvalidProof,
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SyncError::Request(ref url) => write!(f, "request to {} failed", url),
+            SyncError::InvalidHeaders(ref err) => {
+                write!(f, "received an invalid header chain: {}", err)
+            }
+            SyncError::GenesisMismatch => write!(f, "the peer has a different genesis block"),
+            SyncError::MissingBodies(height) => {
+                write!(f, "no peer could serve blocks from height {}", height)
+            }
+            SyncError::BodyMismatch(height) => {
+                write!(f, "block at height {} doesn't match its header", height)
+            }
+            SyncError::InvalidBlock(ref err) => write!(f, "received an invalid block: {}", err),
+            SyncError::InvalidProof => write!(f, "received an invalid merkle proof"),
+        }
+    }
+}
+
+// Where headers and blocks are downloaded from. Peers that speak the binary protocol
+// are asked over it, the others over their http api.
+#[derive(Debug, Clone)]
+pub enum Source {
+    Http(NodeAddr),
+    Wire { addr: String, magic: Magic },
+}
+
+impl Source {
+    // The sources for the peers, using the binary protocol for those the chain knows
+    // to speak it
+    pub fn for_peers(blockchain: &Blockchain, peers: &[NodeAddr]) -> Vec<Source> {
+        peers
+            .iter()
+            .map(|peer| match blockchain.wire_peers.get(peer) {
+                Some(addr) => Source::Wire {
+                    addr: addr.clone(),
+                    magic: blockchain.spec.magic,
+                },
+                None => Source::Http(peer.clone()),
+            })
+            .collect()
+    }
+
+    fn headers(
+        &self,
+        client: &reqwest::Client,
+        from: usize,
+        count: usize,
+    ) -> Result<Vec<BlockHeader>, SyncError> {
+        match *self {
+            Source::Http(ref peer) => fetch(
+                client,
+                &format!("{}/network/headers/{}/{}", peer, from, count),
+            ),
+            Source::Wire { ref addr, magic } => {
+                match wire::request(addr, magic, &Message::GetHeaders { from, count }) {
+                    Ok(Message::Headers(headers)) => Ok(headers),
+                    _ => Err(SyncError::Request(addr.clone())),
+                }
+            }
+        }
+    }
+
+    fn blocks(
+        &self,
+        client: &reqwest::Client,
+        from: usize,
+        count: usize,
+    ) -> Result<Vec<Block>, SyncError> {
+        match *self {
+            Source::Http(ref peer) => fetch(
+                client,
+                &format!("{}/network/blocks/{}/{}", peer, from, count),
+            ),
+            Source::Wire { ref addr, magic } => {
+                match wire::request(addr, magic, &Message::GetBlocks { from, count }) {
+                    Ok(Message::Blocks(blocks)) => Ok(blocks),
+                    _ => Err(SyncError::Request(addr.clone())),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Source::Http(ref peer) => write!(f, "{}", peer),
+            Source::Wire { ref addr, .. } => write!(f, "{}", addr),
+        }
+    }
+}
+
+// Blocks a peer has beyond the part of the chain we share with it
+pub struct Branch {
+    // Height of the branch's first block, which is below our tip if the chains diverge
+    pub fork_height: usize,
+    pub blocks: Vec<Block>,
+}
+
+// Brings the blockchain up to date with the longest chain served by the peers.
+// Returns the number of blocks that were added.
+pub fn sync_chain(blockchain: &mut Blockchain, peers: &[NodeAddr]) -> Result<usize, SyncError> {
+    let hashes: Vec<Digest> = blockchain.chain.iter().map(|block| block.hash()).collect();
+    let sources = Source::for_peers(blockchain, peers);
+
+    match fetch_branch(&hashes, &blockchain.spec, &sources)? {
+        Some(branch) => apply_branch(blockchain, branch),
+        None => Ok(0),
+    }
+}
+
+// Like sync_chain, for the chain shared with the http handlers and the peer protocol.
+// The chain is only locked to read it and to switch over to the new branch, not while
+// the branch is downloaded.
+pub fn catch_up(chain: &SharedChain) -> Result<usize, SyncError> {
+    let (hashes, spec, sources) = {
+        let reader = chain.read().unwrap();
+        let hashes: Vec<Digest> = reader.chain.iter().map(|block| block.hash()).collect();
+        let peers: Vec<NodeAddr> = reader.peers.iter().cloned().collect();
+
+        (
+            hashes,
+            reader.spec.clone(),
+            Source::for_peers(&reader, &peers),
+        )
+    };
+
+    match fetch_branch(&hashes, &spec, &sources)? {
+        Some(branch) => apply_branch(&mut chain.write().unwrap(), branch),
+        None => Ok(0),
+    }
+}
+
+// Downloads the longest branch served by the peers, if it's longer than our chain,
+// whose block hashes are given. Returns None if no peer is ahead of us.
+//
+// Headers are downloaded from every peer and validated before any bodies are
+// requested. They're requested starting at our own tip, so a node that was restarted
+// only downloads what it's missing. The bodies are then fetched in parallel from all
+// peers, starting with the one serving the longest chain.
+pub fn fetch_branch(
+    hashes: &[Digest],
+    spec: &ChainSpec,
+    peers: &[Source],
+) -> Result<Option<Branch>, SyncError> {
+    let client = reqwest::Client::new();
+
+    // The peer serving the longest chain, where its new headers start, and the headers.
+    // The difficulty is fixed, so the chain with the most work is the longest one.
+    let mut best: Option<(usize, usize, Vec<BlockHeader>)> = None;
+    let mut failures = vec![];
+    for (i, peer) in peers.iter().enumerate() {
+        let result = new_headers(
+            &client,
+            peer,
+            hashes.len(),
+            |height| hashes.get(height).cloned(),
+            spec.difficulty,
+        );
+
+        match result {
+            Ok((fork_height, headers)) => {
+                let best_height = best
+                    .as_ref()
+                    .map_or(hashes.len(), |best| best.1 + best.2.len());
+                if fork_height + headers.len() > best_height {
+                    best = Some((i, fork_height, headers));
+                }
+            }
+            Err(err) => {
+                warn!("Couldn't download headers from {}: {}", peer, err);
+                failures.push(err);
+            }
+        }
+    }
+
+    let (source, fork_height, headers) = match best {
+        Some(best) => best,
+        // It's only an error if none of the peers could tell us how far they are
+        None if !peers.is_empty() && failures.len() == peers.len() => {
+            return Err(failures.pop().unwrap())
+        }
+        None => return Ok(None),
+    };
+
+    let mut sources = vec![peers[source].clone()];
+    sources.extend(
+        peers
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != source)
+            .map(|(_, peer)| peer.clone()),
+    );
+    let blocks = download_bodies(&client, &sources, &headers, spec.reward)?;
+
+    Ok(Some(Branch {
+        fork_height,
+        blocks,
+    }))
+}
+
+// Switches the blockchain over to a downloaded branch. The chain may have changed
+// since the branch was downloaded, in which case the branch is only applied if it
+// still builds on the chain and is longer than it. Returns the number of blocks that
+// were added.
+pub fn apply_branch(blockchain: &mut Blockchain, branch: Branch) -> Result<usize, SyncError> {
+    let Branch {
+        mut fork_height,
+        mut blocks,
+    } = branch;
+
+    // Skip the blocks we got hold of in the meantime
+    let known = blocks
+        .iter()
+        .enumerate()
+        .take_while(|&(i, block)| {
+            blockchain
+                .chain
+                .get(fork_height + i)
+                .map(|local| local.hash() == block.hash())
+                .unwrap_or(false)
+        })
+        .count();
+    fork_height += known;
+    blocks.drain(..known);
+
+    if blocks.is_empty() || fork_height + blocks.len() <= blockchain.chain.len() {
+        return Ok(0);
+    }
+
+    let anchor_hash = match fork_height {
+        0 => None,
+        _ => blockchain
+            .chain
+            .get(fork_height - 1)
+            .map(|block| block.hash()),
+    };
+    if blocks[0].header.previous_hash != anchor_hash {
+        debug!("The chain changed while a branch was downloaded, dropping the branch");
+        return Ok(0);
+    }
+
+    let added = blocks.len();
+    blockchain
+        .reorganize(fork_height, blocks)
+        .map_err(SyncError::InvalidBlock)?;
+
+    Ok(added)
+}
+
+// Downloads and validates the headers a peer has beyond the part of the chain we share
+// with it. `hash_at` returns the hash of our own block at a height, if we have one.
+//
+// Returns the height the new headers start at, which is below our tip if the chains
+// diverge, along with the headers. There are none if the peer's chain isn't longer.
+pub fn new_headers<F: Fn(usize) -> Option<Digest>>(
+    client: &reqwest::Client,
+    source: &Source,
+    local_height: usize,
+    hash_at: F,
+    difficulty: usize,
+) -> Result<(usize, Vec<BlockHeader>), SyncError> {
+    // Ask for the headers starting at our tip, so that we can tell whether the peer
+    // builds on it
+    let mut start = local_height.saturating_sub(1);
+    let mut headers = download_headers(client, source, start)?;
+    if headers.is_empty() && local_height > 0 {
+        // The peer's chain is shorter than ours
+        return Ok((local_height, vec![]));
+    }
+
+    let builds_on_tip = match (headers.first(), hash_at(start)) {
+        (Some(first), Some(tip_hash)) => first.hash() == tip_hash,
+        _ => true,
+    };
+
+    if !builds_on_tip {
+        // The chains diverge somewhere below our tip, so look at the peer's whole chain
+        start = 0;
+        headers = download_headers(client, source, start)?;
+    }
+
+    let peer_height = start + headers.len();
+    if peer_height <= local_height {
+        return Ok((local_height, vec![]));
+    }
+
+    // Skip the blocks we already have, up to the first one that differs
+    let common = headers
+        .iter()
+        .enumerate()
+        .take_while(|&(i, header)| hash_at(start + i) == Some(header.hash()))
+        .count();
+    let fork_height = start + common;
+    if fork_height == 0 && local_height > 0 {
+        return Err(SyncError::GenesisMismatch);
+    }
+
+    let anchor = if common > 0 {
+        Some(&headers[common - 1])
+    } else {
+        None
+    };
+    Blockchain::validate_header_chain(anchor, &headers[common..], difficulty)
+        .map_err(SyncError::InvalidHeaders)?;
+
+    let new_headers = headers.split_off(common);
+    Ok((fork_height, new_headers))
+}
+
+pub fn fetch<T: DeserializeOwned>(client: &reqwest::Client, url: &str) -> Result<T, SyncError> {
+    let mut response = client
+        .get(url)
+        .send()
+        .map_err(|_| SyncError::Request(url.to_string()))?;
+
+    if !response.status().is_success() {
+        return Err(SyncError::Request(url.to_string()));
+    }
+
+    response
+        .json()
+        .map_err(|_| SyncError::Request(url.to_string()))
+}
+
+// Downloads the peer's headers from the given height onwards, up to
+// MAX_SYNC_HEADERS of them
+fn download_headers(
+    client: &reqwest::Client,
+    peer: &Source,
+    from: usize,
+) -> Result<Vec<BlockHeader>, SyncError> {
+    let mut headers: Vec<BlockHeader> = vec![];
+
+    loop {
+        let batch = peer.headers(client, from + headers.len(), MAX_HEADERS)?;
+
+        let done = batch.len() < MAX_HEADERS;
+        headers.extend(batch);
+        if done || headers.len() >= MAX_SYNC_HEADERS {
+            headers.truncate(MAX_SYNC_HEADERS);
+            return Ok(headers);
+        }
+    }
+}
+
+// Downloads a batch of bodies, trying each peer in turn until one serves all of them
+fn download_batch(
+    client: &reqwest::Client,
+    peers: &[Source],
+    headers: &[BlockHeader],
+) -> Result<Vec<Block>, SyncError> {
+    let from = headers[0].ind;
+    let count = headers.len();
+    let mut err = SyncError::MissingBodies(from);
+
+    for peer in peers {
+        let blocks = match peer.blocks(client, from, count) {
+            Ok(ref blocks) if blocks.len() == count => blocks.clone(),
+            _ => {
+                warn!(
+                    "Couldn't download blocks {}..{} from {}",
+                    from,
+                    from + count,
+                    peer
+                );
+                continue;
+            }
+        };
+
+        // Peers on another branch serve different blocks at the same heights
+        let mismatch = blocks
+            .iter()
+            .zip(headers.iter())
+            .find(|&(block, header)| block.header != *header);
+        match mismatch {
+            Some((_, header)) => {
+                warn!("{} serves blocks of another branch", peer);
+                err = SyncError::BodyMismatch(header.ind);
+            }
+            None => return Ok(blocks),
+        }
+    }
+
+    Err(err)
+}
+
+// Downloads the bodies belonging to the headers. Batches are spread across the peers
+// and downloaded in parallel, one batch per peer at a time.
+fn download_bodies(
+    client: &reqwest::Client,
+    peers: &[Source],
+    headers: &[BlockHeader],
+    reward: Tulips,
+) -> Result<Vec<Block>, SyncError> {
+    let batches: Vec<&[BlockHeader]> = headers.chunks(MAX_BLOCKS).collect();
+    let mut bodies = Vec::with_capacity(headers.len());
+
+    for wave in batches.chunks(peers.len()) {
+        let handles: Vec<_> = wave
+            .iter()
+            .enumerate()
+            .map(|(i, batch)| {
+                let client = client.clone();
+                let batch = batch.to_vec();
+
+                // Every batch in the wave starts with a different peer, falling back
+                // to the others if it fails
+                let order: Vec<Source> = peers
+                    .iter()
+                    .cycle()
+                    .skip(i)
+                    .take(peers.len())
+                    .cloned()
+                    .collect();
+
+                thread::spawn(move || download_batch(&client, &order, &batch))
+            })
+            .collect();
+
+        for (handle, batch) in handles.into_iter().zip(wave.iter()) {
+            let blocks = match handle.join() {
+                Ok(result) => result?,
+                Err(_) => return Err(SyncError::MissingBodies(batch[0].ind)),
+            };
+
+            for block in blocks {
+                // The header was validated already, but it has to commit to the body
+                block
+                    .validate_body(reward)
+                    .map_err(SyncError::InvalidBlock)?;
+
+                bodies.push(block);
+            }
+        }
+    }
+
+    Ok(bodies)
+}
+
+// Adds a block announced by a peer to the chain and relays it. If the block is further
+// ahead than our tip, or competes with it, the peer's chain is longer than ours, so
+// we switch over to the longest chain our peers have instead. The block isn't turned
+// down in that case.
+pub fn receive_block(chain: &SharedChain, block: Block) -> Result<(), BlockError> {
+    let mut writer = chain.write().unwrap();
+
+    // Peers relay the blocks we announced back to us
+    if writer.block_by_hash(&block.hash()).is_some() {
+        return Ok(());
+    }
+
+    match writer.accept_block(block.clone()) {
+        Ok(()) => {
+            writer.broadcast_block(block);
+            Ok(())
+        }
+        Err(ref err) if err.is_ahead() => {
+            let chain = chain.clone();
+            thread::spawn(move || {
+                if let Err(err) = catch_up(&chain) {
+                    warn!("Couldn't catch up with the network: {}", err);
+                }
+            });
+
+            Ok(())
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// Adds a transaction relayed by a peer to the pending ones, and relays it in turn.
+// Peers relay our transactions back to us, which are skipped without being checked
+// again, and without counting as rejected.
+pub fn receive_transaction(chain: &SharedChain, transaction: Transaction) -> Result<(), TxError> {
+    let mut writer = chain.write().unwrap();
+    if writer.pending_transactions.contains(&transaction) {
+        return Ok(());
+    }
+
+    writer.append_transaction(transaction.clone())?;
+    writer.broadcast_transaction(transaction);
+
+    Ok(())
+}
@@ -1,34 +1,94 @@
 use sodiumoxide::crypto::sign;
-use super::{address, blockchain, transaction};
+use tulipchain::{address, base58, blockchain, hex, merkle, metrics, status, transaction};
+use tulipchain::{wallet, wire};
+use tulipchain::ServerConfig;
+use super::full_node;
+use std::{env, fs};
+use std::io::{Cursor, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, RwLock};
+use chrono::prelude::*;
+use chrono::Duration;
+use serde_json;
+use rand;
+use rocket;
+use log::{Level, LevelFilter};
+use proptest::prelude::*;
+use proptest::test_runner::TestRunner;
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::Client;
 
-use transaction::Transaction;
-use address::Address;
+use tulipchain::transaction::{Transaction, TxError};
+use tulipchain::address::{Address, AddressError};
+use tulipchain::blockchain::{Block, BlockError, Blockchain, DecodeError, BLOCK_VERSION};
+use tulipchain::store::ChainStore;
+use tulipchain::spec::{Allocation, ChainSpec, SpecError};
+use tulipchain::bootstrap::{BootstrapError, BootstrapOptions};
+use tulipchain::wire::{Message, WireError};
+use tulipchain::wallet::{Wallet, WalletError};
+use tulipchain::light::LightChain;
+use tulipchain::sync::{self, Branch};
+use tulipchain::index::Balance;
+use tulipchain::config::{ConfigError, NodeConfig};
+use tulipchain::logging::{self, LogError, LogFilter, LogFormat};
+use tulipchain::miner::MiningState;
+use tulipchain::clock::{ManualClock, SharedClock};
+use tulipchain::keys::SeededKeys;
+use tulipchain::metrics::RequestMetrics;
+use tulipchain::sim::{Miner, Partition, SimConfig, Simulation};
+use auth::ApiAuth;
+use harness::Network;
+
+// A block with fixed contents and keys, whose encoding is pinned down by a golden file
+fn golden_block() -> Block {
+    let (sender_key, signing_key) = sign::keypair_from_seed(&sign::Seed([1; sign::SEEDBYTES]));
+    let (recipient_key, _) = sign::keypair_from_seed(&sign::Seed([2; sign::SEEDBYTES]));
+
+    let mut transaction = Transaction::new(Some(sender_key), recipient_key, 10, 0);
+    transaction.sign(&signing_key);
+
+    Block::new(
+        1,
+        Utc.ymd(2018, 6, 1).and_hms(12, 0, 0),
+        vec![transaction],
+        Some(vec![7; 32]),
+        Transaction::create_coinbase_transaction(recipient_key, 5, 1),
+        1234,
+    )
+}
 
 // After signing a transaction with a users private key, we should be able to verify
 // that user signed it. If the user didn't, the verification should fail.
 #[test]
 fn test_signing() {
     // Create a pair of address
-    let mut src_address = Address::new();
+    let src_address = Address::new();
     let dest_address = Address::new();
 
     // Create a new transaction from source to destination
-    // Because the sending account has 0 balance, the sending value is 0 tulips
-    let transaction = src_address.new_transaction(0, dest_address.public_key);
+    let transaction = src_address.new_transaction(0, dest_address.public_key, 0);
 
     // Verify that the transaction signature was signed by the private key of the sender
-    assert!(transaction.unwrap().verify_digest());
+    assert!(transaction.verify_digest().is_ok());
 
     // Now, check that dest_address's attempt at tulip theft(!) is detected
-    let mut forged_transaction =
-        Transaction::new(Some(src_address.public_key), dest_address.public_key, 100);
+    let mut forged_transaction = Transaction::new(
+        Some(src_address.public_key),
+        dest_address.public_key,
+        100,
+        0,
+    );
 
     // The desination generates a private key and uses it to sign the transaction
     let (_public_key, private_key) = sign::gen_keypair();
     forged_transaction.sign(&private_key);
 
     // Tulip theft averted!
-    assert!(!forged_transaction.verify_digest());
+    assert!(forged_transaction.verify_digest() == Err(TxError::BadSignature));
+
+    // Coinbase transactions have no one to sign them
+    let coinbase = Transaction::create_coinbase_transaction(dest_address.public_key, 5, 1);
+    assert!(coinbase.verify_digest() == Err(TxError::UnknownCoinbase));
 }
 
 #[test]
@@ -37,45 +97,1787 @@ fn test_proof_of_work() {
     let mut blockchain = blockchain::Blockchain::new();
 
     // Check that the chain with only the genesis block is valid
-    assert!(blockchain.is_valid_chain());
+    assert!(blockchain.validate_chain().is_ok());
 
-    let nonce = blockchain.chain[0].nonce;
-    blockchain.chain[0].nonce = 10;
+    let nonce = blockchain.chain[0].header.nonce;
+    blockchain.chain[0].header.nonce = 10;
 
     // Check that replacing the nonce invalidates the chain
-    assert!(!blockchain.is_valid_chain());
+    assert!(blockchain.validate_chain() == Err(BlockError::BadPow { height: 0 }));
 
     // Correc the nonce and ensure the chain is valid again
-    blockchain.chain[0].nonce = nonce;
-    assert!(blockchain.is_valid_chain());
+    blockchain.chain[0].header.nonce = nonce;
+    assert!(blockchain.validate_chain().is_ok());
 
     // Create a new zero-value transaction between two addresses and add it to the chain
-    let mut src_address = Address::new();
+    let src_address = Address::new();
     let dest_address = Address::new();
-    let transaction = src_address
-        .new_transaction(0, dest_address.public_key)
-        .unwrap();
+    let transaction = src_address.new_transaction(0, dest_address.public_key, 0);
 
     // Ensure the transaction is added to the list of pending transactions successfully
     let transaction_clone = transaction.clone();
-    assert!(blockchain.append_transaction(transaction));
+    assert!(blockchain.append_transaction(transaction).is_ok());
     assert!(transaction_clone == blockchain.pending_transactions[0]);
 
-    let last_nonce = blockchain.get_last_nonce();
-    // Find a nonce to add the block to the chain
-    let nonce = blockchain::Blockchain::find_nonce(last_nonce, &None);
-
+    // Find a nonce for the next block, which commits to the transaction
     let payout_addr = blockchain.address.clone().unwrap().public_key;
-    // Add a new block to the chain using the nonce that was found
-    blockchain.append_block(nonce, None, payout_addr);
+    let mut block = blockchain.next_block(payout_addr);
+    let difficulty = blockchain.spec.difficulty;
+    block.header.nonce = blockchain::Blockchain::find_nonce(&block.header, difficulty);
+    assert!(block.header.meets_difficulty(difficulty));
+
+    // Add the new block to the chain
+    blockchain.append_block(block);
 
     // Ensure that the block got added and that it contains the transaction
     assert!(blockchain.chain.len() == 2);
     // With a mining abstraction, there will be an additional coinbase transaction
-    assert!(blockchain.chain.last().unwrap().transactions.len() == 1);
+    assert!(blockchain.chain.last().unwrap().body.transactions.len() == 1);
+    assert!(blockchain.validate_chain().is_ok());
 }
 
 #[test]
 fn test_create_and_broadcast_transaction() {
+    let network = Network::connected(3);
+    network.mine(0);
+    assert!(network.assert_converged() == 1);
+
+    // Node 0 pays out of its reward, and relays the payment to its peers
+    let transaction = network.pay(0, 1, 3);
+    network.wait_until("the peers hear of the payment", |network| {
+        network
+            .nodes
+            .iter()
+            .all(|node| node.has_pending(&transaction))
+    });
+
+    // Whoever mines it, every node ends up with it in the chain
+    network.mine(2);
+    assert!(network.assert_converged() == 2);
+    let recipient = network.nodes[1].address.public_key;
+    for node in &network.nodes {
+        assert!(node.balance(&recipient) == 3);
+        assert!(!node.has_pending(&transaction));
+    }
+}
+
+// Headers served to a synchronizing node should link up from the genesis block,
+// or from the tip the node is resuming from
+#[test]
+fn test_header_chain_validation() {
+    let mut blockchain = Blockchain::new();
+    blockchain.mine();
+    blockchain.mine();
+
+    let headers = blockchain.headers(0, 10);
+    let difficulty = blockchain.spec.difficulty;
+    assert!(headers.len() == 3);
+    assert!(Blockchain::validate_header_chain(None, &headers, difficulty).is_ok());
+    assert!(
+        Blockchain::validate_header_chain(Some(&headers[0]), &headers[1..], difficulty).is_ok()
+    );
+
+    // A run of headers that skips a block doesn't link up
+    assert!(
+        Blockchain::validate_header_chain(None, &headers[1..], difficulty)
+            == Err(BlockError::BadHeight {
+                height: 1,
+                expected: 0,
+            })
+    );
+    assert!(
+        Blockchain::validate_header_chain(Some(&headers[0]), &headers[2..], difficulty)
+            == Err(BlockError::BadHeight {
+                height: 2,
+                expected: 1,
+            })
+    );
+
+    // Nor does a header that points elsewhere
+    let mut forked = headers[2].clone();
+    forked.previous_hash = Some(headers[0].hash());
+    forked.nonce = Blockchain::find_nonce(&forked, difficulty);
+    assert!(
+        Blockchain::validate_header_chain(Some(&headers[1]), &[forked], difficulty)
+            == Err(BlockError::BadPrevHash { height: 2 })
+    );
+}
+
+#[test]
+fn test_chain_store() {
+    let dir = env::temp_dir().join(format!("tulipchain-{}", rand::random::<u32>()));
+    let store = ChainStore::new(dir.clone());
+    assert!(store.load().unwrap().is_none());
+
+    // Appending a block persists it on top of the chain
+    let mut blockchain = Blockchain::new();
+    blockchain.store = Some(store.clone());
+    blockchain.persist();
+    blockchain.mine();
+    blockchain.mine();
+
+    // The nonces are persisted with the blocks, so the reloaded chain is still valid
+    let blocks = store.load().unwrap().unwrap();
+    assert!(blocks.len() == 3);
+    assert!(blocks[1].header.nonce == blockchain.chain[1].header.nonce);
+    assert!(Blockchain::from_blocks(ChainSpec::dev(), blocks)
+        .validate_chain()
+        .is_ok());
+
+    // Reorganizing rewrites the chain, and what's appended afterwards builds on it
+    let mut rival = Blockchain::new();
+    for _ in 0..3 {
+        rival.mine();
+    }
+    let branch = rival.chain[1..].to_vec();
+    assert!(blockchain.reorganize(1, branch).is_ok());
+    blockchain.mine();
+    let blocks = store.load().unwrap().unwrap();
+    assert!(blocks.len() == 5);
+    assert!(blocks[1].hash() == rival.chain[1].hash());
+    assert!(blocks[4].hash() == blockchain.chain[4].hash());
+
+    // A block that was only partly appended is left out
+    fs::OpenOptions::new()
+        .append(true)
+        .open(dir.join("blocks.log"))
+        .unwrap()
+        .write_all(b"{\"header\":")
+        .unwrap();
+    assert!(store.load().unwrap().unwrap().len() == 5);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+// A node that can't reach any seed only starts if it has a snapshot to fall back to
+#[test]
+fn test_bootstrap_snapshot_fallback() {
+    let dir = env::temp_dir().join(format!("tulipchain-{}", rand::random::<u32>()));
+    let mut blockchain = Blockchain::new();
+    blockchain.mine();
+    ChainStore::new(dir.clone())
+        .save(&blockchain.chain)
+        .unwrap();
+
+    let config = ServerConfig {
+        address: String::from("localhost"),
+        port: 8000,
+        p2p_port: None,
+    };
+    let options = |snapshot| BootstrapOptions {
+        // Nothing listens on this port
+        seeds: vec![String::from("http://127.0.0.1:1")],
+        attempts: 1,
+        snapshot,
+        store: None,
+        spec: ChainSpec::dev(),
+    };
+
+    match Blockchain::init_chain(options(None), &config) {
+        Err(BootstrapError::Request(_)) => {}
+        _ => panic!("Expected the seed to be unreachable"),
+    }
+
+    let chain = Blockchain::init_chain(options(Some(dir.join("chain.json"))), &config)
+        .ok()
+        .unwrap();
+    assert!(chain.chain.len() == 2);
+    assert!(chain.chain[1].hash() == blockchain.chain[1].hash());
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+// Blocks mined by one node are accepted by another node following the same chain,
+// but only if they build on its tip
+#[test]
+fn test_accept_block() {
+    let mut miner = Blockchain::new();
+    let mut follower = miner.clone();
+
+    let block = miner.mine();
+    assert!(follower.accept_block(block.clone()).is_ok());
+    assert!(follower.chain.len() == 2);
+
+    // The same block doesn't extend the follower's tip anymore
+    let err = follower.accept_block(block).unwrap_err();
+    assert!(
+        err == BlockError::BadHeight {
+            height: 1,
+            expected: 2,
+        }
+    );
+    assert!(!err.is_ahead());
+
+    // Neither does a block that skips one, which means the follower is behind
+    miner.mine();
+    let mut block = miner.mine();
+    assert!(follower.accept_block(block.clone()).unwrap_err().is_ahead());
+
+    // So is a block on a branch that forks off below the follower's tip, and is one
+    // block longer than its chain
+    let mut rival = Blockchain::new();
+    rival.mine();
+    let err = follower.accept_block(rival.mine()).unwrap_err();
+    assert!(err == BlockError::BadPrevHash { height: 2 });
+    assert!(err.is_ahead());
+
+    // A block without proof of work is turned down before it's looked at any further,
+    // so it can't pass for a sign that the follower is behind
+    let difficulty = follower.spec.difficulty;
+    let mut junk = rival.mine();
+    while junk.header.meets_difficulty(difficulty) {
+        junk.header.nonce += 1;
+    }
+    let err = follower.accept_block(junk).unwrap_err();
+    assert!(err == BlockError::BadPow { height: 3 });
+    assert!(!err.is_ahead());
+    assert!(follower.metrics.blocks_rejected.get(err.reason()) == Some(&1));
+
+    // Blocks in an unknown format are rejected even if they'd fit
+    assert!(follower.accept_block(miner.chain[2].clone()).is_ok());
+    block.header.version = BLOCK_VERSION + 1;
+    block.header.nonce = Blockchain::find_nonce(&block.header, difficulty);
+    assert!(
+        follower.accept_block(block)
+            == Err(BlockError::UnsupportedVersion {
+                height: 3,
+                version: BLOCK_VERSION + 1,
+            })
+    );
+}
+
+#[test]
+fn test_wire_framing() {
+    let message = Message::GetHeaders { from: 3, count: 10 };
+
+    let magic = ChainSpec::dev().magic;
+    let mut frame = vec![];
+    wire::write_message(&mut frame, magic, &message).unwrap();
+    assert!(&frame[0..4] == b"TLIP");
+
+    match wire::read_message(&mut Cursor::new(frame.clone()), magic).unwrap() {
+        Message::GetHeaders { from: 3, count: 10 } => {}
+        _ => panic!("Message didn't round-trip"),
+    }
+
+    // Frames from other networks are rejected
+    match wire::read_message(&mut Cursor::new(frame), *b"TEST") {
+        Err(WireError::BadMagic) => {}
+        _ => panic!("Expected bad magic bytes"),
+    }
+}
+
+#[test]
+fn test_wire_headers_request() {
+    let mut blockchain = Blockchain::new();
+    blockchain.mine();
+    let expected = blockchain.headers(0, 10);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    wire::serve(listener, Arc::new(RwLock::new(blockchain)));
+
+    let magic = ChainSpec::dev().magic;
+    let mut stream = wire::connect(&addr, magic, 0, None).unwrap();
+    let request = Message::GetHeaders { from: 0, count: 10 };
+    wire::write_message(&mut stream, magic, &request).unwrap();
+
+    match wire::read_message(&mut stream, magic).unwrap() {
+        Message::Headers(headers) => assert!(headers == expected),
+        _ => panic!("Expected headers"),
+    }
+}
+
+// Peers that speak the binary protocol are synchronized with over it, so the http
+// address they registered with isn't contacted
+#[test]
+fn test_wire_sync() {
+    let mut blockchain = Blockchain::new();
+    blockchain.mine();
+    blockchain.mine();
+    let tip = blockchain.chain[2].hash();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    wire::serve(listener, Arc::new(RwLock::new(blockchain)));
+
+    let peer = "http://127.0.0.1:1".to_string();
+    let mut follower = Blockchain::new();
+    follower.register_wire_peer(peer.clone(), addr);
+
+    assert!(sync::sync_chain(&mut follower, &[peer]).unwrap() == 2);
+    assert!(follower.chain[2].hash() == tip);
+}
+
+// Nodes started on the same network share their genesis block, while a different
+// spec results in a different genesis block
+#[test]
+fn test_chain_spec_genesis() {
+    let dev_spec = ChainSpec::dev();
+    assert!(dev_spec.validate().is_ok());
+
+    let genesis_hash = Blockchain::new().genesis_hash();
+    assert!(Blockchain::new().chain[0].hash() == genesis_hash);
+
+    // Changing the genesis block takes a new proof of work
+    let mut spec = ChainSpec::dev();
+    spec.genesis.premine.push(Allocation {
+        addr: Address::new().public_key,
+        value: 100,
+    });
+    spec.mine_genesis();
+    assert!(spec.validate().is_ok());
+    let blockchain = Blockchain::with_spec(spec);
+    assert!(blockchain.genesis_hash() != genesis_hash);
+    assert!(blockchain.chain[0].body.transactions[0].value == 100);
+    assert!(blockchain.validate_chain().is_ok());
+
+    // The genesis nonce has to satisfy the difficulty
+    let mut spec = ChainSpec::dev();
+    spec.genesis.nonce += 1;
+    match spec.validate() {
+        Err(SpecError::GenesisNonce) => {}
+        _ => panic!("Expected an invalid genesis nonce"),
+    }
+
+    // Every genesis transaction needs an id of its own
+    let mut spec = ChainSpec::dev();
+    let allocation = Allocation {
+        addr: Address::new().public_key,
+        value: 100,
+    };
+    spec.genesis.premine = vec![allocation.clone(), allocation];
+    spec.mine_genesis();
+    match spec.validate() {
+        Err(SpecError::DuplicateAllocation) => {}
+        _ => panic!("Expected a duplicate allocation"),
+    }
+}
+
+// A wallet restored from its seed phrase derives the same keypairs, and can spend
+// from them
+#[test]
+fn test_wallet_from_phrase() {
+    let mut wallet = Wallet::generate();
+    let receiving_addr = wallet.new_address();
+
+    let restored = Wallet::from_phrase(&wallet.phrase()).unwrap();
+    assert!(restored.address(0).public_key == wallet.address(0).public_key);
+    assert!(restored.address(1).public_key == receiving_addr.public_key);
+    assert!(receiving_addr.public_key != wallet.address(0).public_key);
+
+    let sender = restored.address(1);
+    let transaction = sender.new_transaction(0, wallet.address(0).public_key, 0);
+    assert!(transaction.sender_addr == Some(receiving_addr.public_key));
+    assert!(transaction.verify_digest().is_ok());
+}
+
+// The wallet file holds the seed phrase, so it's only readable by its owner, and it's
+// replaced as a whole when the wallet changes
+#[cfg(unix)]
+#[test]
+fn test_wallet_file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = env::temp_dir().join(format!("tulipchain-{}", rand::random::<u32>()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("wallet.json");
+
+    let (mut wallet, created) = Wallet::open(path.clone()).unwrap();
+    assert!(created);
+    wallet.new_address();
+    wallet.persist().unwrap();
+    assert!(fs::metadata(&path).unwrap().permissions().mode() & 0o777 == 0o600);
+    assert!(!dir.join("wallet.json.tmp").exists());
+
+    let (reopened, created) = Wallet::open(path).unwrap();
+    assert!(!created);
+    assert!(reopened.phrase() == wallet.phrase());
+    assert!(reopened.addresses().len() == 2);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_wallet_phrase_typos() {
+    let phrase = Wallet::generate().phrase();
+    let mut words: Vec<&str> = phrase.split(' ').collect();
+
+    match Wallet::from_phrase(&words[1..].join(" ")) {
+        Err(WalletError::WordCount(_)) => {}
+        _ => panic!("Expected a missing word to be detected"),
+    }
+
+    let first_word = words[0];
+    words[0] = "notaword";
+    match Wallet::from_phrase(&words.join(" ")) {
+        Err(WalletError::UnknownWord(_)) => {}
+        _ => panic!("Expected an unknown word to be detected"),
+    }
+
+    // The last word is the checksum of the others
+    words[0] = first_word;
+    let last = words.len() - 1;
+    words[last] = if words[last] == "acid" {
+        "acorn"
+    } else {
+        "acid"
+    };
+    match Wallet::from_phrase(&words.join(" ")) {
+        Err(WalletError::Checksum) => {}
+        _ => panic!("Expected a checksum mismatch"),
+    }
+}
+
+#[test]
+fn test_wallet_recovery() {
+    let mut wallet = Wallet::generate();
+    let used: Vec<_> = (0..5).map(|_| wallet.new_address().public_key).collect();
+
+    // Only the last handed out address has been used
+    let mut restored = Wallet::from_phrase(&wallet.phrase()).unwrap();
+    restored.recover(|addr| *addr == used[4]);
+    assert!(restored.addresses().len() == 6);
+
+    // Addresses beyond the gap aren't found
+    let mut wallet = Wallet::generate();
+    let far_addr = wallet.address(wallet::RECOVERY_GAP + 5).public_key;
+    let mut restored = Wallet::from_phrase(&wallet.phrase()).unwrap();
+    restored.recover(|addr| *addr == far_addr);
+    assert!(restored.addresses().len() == 1);
+}
+
+// The binary encoding of a block is part of the protocol, so it mustn't change
+// without bumping the block version
+#[test]
+fn test_block_encoding_golden() {
+    let golden = include_bytes!("testdata/block_v5.bin");
+    let block = golden_block();
+
+    assert!(block.encode() == &golden[..]);
+    assert!(Block::decode(golden).unwrap() == block);
+
+    // The signature survives decoding, so the transaction can still be verified
+    assert!(Block::decode(golden).unwrap().body.transactions[0]
+        .verify_digest()
+        .is_ok());
+}
+
+#[test]
+fn test_block_decode_version() {
+    let mut bytes = golden_block().encode();
+    bytes[0] += 1;
+    match Block::decode(&bytes) {
+        Err(DecodeError::UnsupportedVersion(version)) if version == BLOCK_VERSION + 1 => {}
+        _ => panic!("Expected an unsupported version"),
+    }
+
+    match Block::decode(&bytes[0..2]) {
+        Err(DecodeError::Malformed) => {}
+        _ => panic!("Expected a malformed block"),
+    }
+
+    bytes[0] -= 1;
+    match Block::decode(&bytes[0..bytes.len() - 1]) {
+        Err(DecodeError::Malformed) => {}
+        _ => panic!("Expected a truncated block to be malformed"),
+    }
+}
+
+// A chain served as JSON keeps its nonces and signatures, so the receiving node can
+// validate it
+#[test]
+fn test_chain_json_round_trip() {
+    let mut blockchain = Blockchain::new();
+    let src_address = Address::new();
+    let transaction = src_address.new_transaction(0, Address::new().public_key, 0);
+    assert!(blockchain.append_transaction(transaction).is_ok());
+    blockchain.mine();
+
+    let serialized = serde_json::to_string(&blockchain).unwrap();
+    let received: Blockchain = serde_json::from_str(&serialized).unwrap();
+
+    assert!(received.chain == blockchain.chain);
+    assert!(received.chain[1].hash() == blockchain.chain[1].hash());
+    assert!(received.chain[1].validate_transactions().is_ok());
+    assert!(Blockchain::from_blocks(ChainSpec::dev(), received.chain)
+        .validate_chain()
+        .is_ok());
+}
+
+// Every leaf of trees of various shapes can be proven, but only against its own root
+#[test]
+fn test_merkle_proofs() {
+    for count in 1..10 {
+        let leaves: Vec<Vec<u8>> = (0..count).map(|i| vec![i as u8; 32]).collect();
+        let root = merkle::root(&leaves);
+
+        for (i, leaf) in leaves.iter().enumerate() {
+            let path = merkle::prove(&leaves, i).unwrap();
+            assert!(merkle::verify(leaf, &path, &root));
+            assert!(!merkle::verify(&[0xff; 32], &path, &root));
+        }
+
+        assert!(merkle::prove(&leaves, count).is_none());
+    }
+
+    let leaves = vec![vec![1; 32], vec![2; 32]];
+    let path = merkle::prove(&leaves, 0).unwrap();
+    assert!(!merkle::verify(
+        &leaves[0],
+        &path,
+        &merkle::root(&leaves[1..])
+    ));
+}
+
+#[test]
+fn test_transaction_proof() {
+    let mut blockchain = Blockchain::new();
+    let src_address = Address::new();
+    let transaction = src_address.new_transaction(0, Address::new().public_key, 0);
+    assert!(blockchain.append_transaction(transaction.clone()).is_ok());
+    blockchain.mine();
+    blockchain.mine();
+
+    let proof = blockchain.transaction_proof(&transaction.id()).unwrap();
+    assert!(proof.height == 1);
+    assert!(proof.block_hash == blockchain.chain[1].hash());
+    assert!(proof.merkle_root == blockchain.chain[1].header.merkle_root);
+    assert!(proof.verify());
+
+    let txid = hex::encode(&transaction.id());
+    assert!(hex::decode(&txid).unwrap() == transaction.id());
+    assert!(hex::decode(&txid.to_uppercase()).unwrap() == transaction.id());
+    assert!(hex::decode("abc").is_none());
+    assert!(hex::decode("zz").is_none());
+
+    assert!(blockchain.transaction_proof(&[0; 32]).is_none());
+
+    // A block whose transactions were tampered with doesn't match its merkle root
+    let mut block = blockchain.chain[1].clone();
+    block.body.transactions[0].value = 1;
+    assert!(!block.has_valid_merkle_root());
+}
+
+// A light node accepts transactions whose proofs lead to the merkle root of one of its
+// headers, and nothing else
+#[test]
+fn test_light_chain_proofs() {
+    let mut blockchain = Blockchain::new();
+    let src_address = Address::new();
+    let dest_address = Address::new();
+    let transaction = src_address.new_transaction(0, dest_address.public_key, 0);
+    assert!(blockchain.append_transaction(transaction).is_ok());
+    blockchain.mine();
+
+    let mut light = LightChain::new(ChainSpec::dev(), vec![]);
+    assert!(light.headers == blockchain.headers(0, 1));
+
+    let proofs = blockchain.address_proofs(&dest_address.public_key);
+    assert!(proofs.len() == 1);
+
+    // Without the header of the block, the transaction can't be checked
+    assert!(!light.is_proven(&proofs[0]));
+    light.headers = blockchain.headers(0, 10);
+    assert!(light.is_proven(&proofs[0]));
+
+    let mut forged = proofs[0].clone();
+    forged.transaction.value = 100;
+    assert!(!light.is_proven(&forged));
+
+    // The miner's balance is made up of the block reward
+    let miner_addr = blockchain.address.clone().unwrap().public_key;
+    light.transactions = blockchain.address_proofs(&miner_addr);
+    assert!(light
+        .transactions
+        .iter()
+        .all(|proven| light.is_proven(proven)));
+    assert!(light.balance(&[miner_addr]) == blockchain.spec.reward);
+    assert!(light.balance(&[dest_address.public_key]) == 0);
+}
+
+// Every reward the wallet wins counts towards the balance a light node reports
+#[test]
+fn test_light_balance_of_rewards() {
+    let network = Network::connected(1);
+    for _ in 0..3 {
+        network.mine(0);
+    }
+
+    let node = &network.nodes[0];
+    let mut light = LightChain::new(ChainSpec::dev(), vec![node.url.clone()]);
+    assert!(light.sync_headers().unwrap() == 3);
+
+    let addrs = [node.address.public_key];
+    light.check_transactions(&addrs).unwrap();
+    assert!(light.transactions.len() == 3);
+    assert!(light.balance(&addrs) == 3 * ChainSpec::dev().reward);
+}
+
+// A light node reports the same height and tip as the full node it follows
+#[test]
+fn test_light_status_matches_tip() {
+    let mut blockchain = Blockchain::new();
+    blockchain.mine();
+    blockchain.mine();
+
+    let mut light = LightChain::new(ChainSpec::dev(), vec![]);
+    light.headers = blockchain.headers(0, 10);
+    let status = light.status(&[]);
+
+    let chain = Arc::new(RwLock::new(blockchain));
+    let wallet = Wallet::generate();
+    let client = Client::new(full_node(rocket::ignite(), chain, wallet, ApiAuth(None))).unwrap();
+    let mut response = client.get("/chain/tip").dispatch();
+    assert!(response.status() == Status::Ok);
+    let tip: serde_json::Value = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+
+    assert!(status.height == 2);
+    assert!(tip["height"].as_u64() == Some(status.height as u64));
+    assert!(tip["hash"] == serde_json::Value::String(hex::encode(&status.tip)));
+}
+
+// The header commits to the body, so a header is enough to identify a block and check
+// its proof of work
+#[test]
+fn test_block_header_and_body() {
+    let mut blockchain = Blockchain::new();
+    let block = blockchain.mine();
+
+    assert!(block.hash() == block.header.hash());
+    assert!(block.header.meets_difficulty(blockchain.spec.difficulty));
+    assert!(block.header.previous_hash == Some(blockchain.chain[0].hash()));
+    assert!(blockchain.header_at(1) == Some(block.header.clone()));
+    assert!(blockchain.header_at(2).is_none());
+    assert!(blockchain.block_by_hash(&block.hash()) == Some(&block));
+    assert!(blockchain.block_by_hash(&[0; 32]).is_none());
+
+    // A body swapped for another one doesn't match the header's merkle root
+    let mut tampered = block.clone();
+    tampered.body.coinbase_transaction.value += 1;
+    assert!(!tampered.has_valid_merkle_root());
+    assert!(tampered.hash() == block.hash());
+
+    let mut follower = Blockchain::new();
+    assert!(follower.accept_block(tampered) == Err(BlockError::BadMerkleRoot { height: 1 }));
+    assert!(follower.accept_block(block).is_ok());
+}
+
+// JSON carries digests as hex strings and public keys as addresses, while the binary
+// encoding keeps them as bytes
+#[test]
+fn test_json_hex_encoding() {
+    let block = golden_block();
+    let json = serde_json::to_value(&block).unwrap();
+
+    let merkle_root = hex::encode(&block.header.merkle_root);
+    assert!(json["header"]["merkle_root"] == serde_json::Value::String(merkle_root));
+    assert!(json["header"]["previous_hash"] == serde_json::Value::String("07".repeat(32)));
+
+    let transaction = &block.body.transactions[0];
+    let sender_addr = address::encode(&transaction.sender_addr.unwrap());
+    assert!(
+        json["body"]["transactions"][0]["sender_addr"] == serde_json::Value::String(sender_addr)
+    );
+    assert!(json["body"]["coinbase_transaction"]["sender_addr"].is_null());
+
+    let decoded: Block = serde_json::from_value(json).unwrap();
+    assert!(decoded == block);
+
+    let mut json = serde_json::to_value(&block).unwrap();
+    json["header"]["merkle_root"] = serde_json::Value::String(String::from("not hex"));
+    assert!(serde_json::from_value::<Block>(json).is_err());
+}
+
+#[test]
+fn test_cumulative_work() {
+    let mut blockchain = Blockchain::new();
+    assert!(blockchain.cumulative_work() == 1 << 16);
+
+    blockchain.mine();
+    assert!(blockchain.cumulative_work() == 2 << 16);
+
+    // Work beyond what fits is capped rather than wrapping around
+    blockchain.spec.difficulty = 32;
+    assert!(blockchain.cumulative_work() == u64::max_value());
+}
+
+// The address index follows the chain, and pending transactions are counted on top
+#[test]
+fn test_address_index() {
+    let mut blockchain = Blockchain::new();
+    let node_addr = blockchain.address.clone().unwrap();
+    let dest_addr = Address::new().public_key;
+
+    blockchain.mine();
+    blockchain.mine();
+    let balance = blockchain.balance(&node_addr.public_key);
+    assert!(balance.confirmed == 10 && balance.pending == 10);
+
+    let transaction = node_addr.new_transaction(3, dest_addr, 0);
+    assert!(blockchain.append_transaction(transaction.clone()).is_ok());
+
+    let balance = blockchain.balance(&node_addr.public_key);
+    assert!(balance.confirmed == 10 && balance.pending == 7 && balance.available == 7);
+    let balance = blockchain.balance(&dest_addr);
+    assert!(balance.confirmed == 0 && balance.pending == 3 && balance.available == 0);
+
+    // Pending transactions come first, then the newest confirmed ones
+    let history = blockchain.address_history(&node_addr.public_key, 0, 10);
+    assert!(history.len() == 3);
+    assert!(history[0].height.is_none() && history[0].txid == transaction.id());
+    assert!(history[1].height == Some(2) && history[2].height == Some(1));
+
+    let page = blockchain.address_history(&node_addr.public_key, 1, 1);
+    assert!(page.len() == 1 && page[0].height == Some(2));
+
+    blockchain.mine();
+    let balance = blockchain.balance(&dest_addr);
+    assert!(balance.confirmed == 3 && balance.pending == 3);
+    let history = blockchain.address_history(&dest_addr, 0, 10);
+    assert!(history.len() == 1 && history[0].height == Some(3));
+
+    // Dropping blocks from the chain drops them from the index too
+    let blocks = blockchain.chain[1..2].to_vec();
+    assert!(blockchain.reorganize(1, blocks).is_ok());
+    assert!(blockchain.balance(&dest_addr).confirmed == 0);
+    assert!(blockchain.balance(&node_addr.public_key).confirmed == 5);
+}
+
+// Addresses survive a round trip through their textual form, and typos are caught by
+// the checksum
+#[test]
+fn test_address_encoding() {
+    assert!(base58::encode(&[0, 0, 1, 2]) == "115T");
+    assert!(base58::decode("115T").unwrap() == vec![0, 0, 1, 2]);
+    assert!(base58::decode("0OIl").is_none());
+
+    let public_key = Address::from_seed(&sign::Seed([1; 32])).public_key;
+    let encoded = address::encode(&public_key);
+    assert!(address::decode(&encoded) == Ok(public_key));
+
+    // Swapping a character for another one of the alphabet
+    let last = encoded.chars().last().unwrap();
+    let typo = format!(
+        "{}{}",
+        &encoded[..encoded.len() - 1],
+        if last == '2' { '3' } else { '2' }
+    );
+    assert!(address::decode(&typo) == Err(AddressError::Checksum));
+
+    assert!(address::decode("not an address") == Err(AddressError::Encoding));
+    assert!(address::decode(&encoded[1..]).is_err());
+    assert!(address::decode(&base58::encode(&public_key[..])) == Err(AddressError::Length(32)));
+
+    let json = serde_json::to_value(&transaction::PartialTransaction {
+        recipient_addr: public_key,
+        value: 3,
+    })
+    .unwrap();
+    assert!(json["recipient_addr"] == serde_json::Value::String(encoded));
+}
+
+// Mines three blocks ten minutes apart, with keys derived from the seed
+fn mine_deterministically(seed: &[u8]) -> Blockchain {
+    let clock = Arc::new(ManualClock::new(Utc.ymd(2018, 6, 1).and_hms(12, 0, 0)));
+    let mut blockchain = Blockchain::with_sources(
+        ChainSpec::dev(),
+        SharedClock::new(clock.clone()),
+        &mut SeededKeys::new(seed),
+    );
+    for _ in 0..3 {
+        clock.advance(Duration::minutes(10));
+        blockchain.mine();
+    }
+
+    blockchain
+}
+
+// Chains mining with the same clock and keys end up identical, down to the bytes
+#[test]
+fn test_deterministic_chain() {
+    let first = mine_deterministically(b"tulips");
+    let second = mine_deterministically(b"tulips");
+    assert!(first.chain.len() == 4);
+    assert!(first.chain[3].header.timestamp == Utc.ymd(2018, 6, 1).and_hms(12, 30, 0));
+    for (a, b) in first.chain.iter().zip(&second.chain) {
+        assert!(a.encode() == b.encode());
+    }
+
+    // The keys are derived from the seed, and so are the rewarded addresses
+    let mut keys = SeededKeys::new(b"tulips");
+    let node_addr = first.address.as_ref().unwrap().public_key;
+    assert!(Address::generate(&mut keys).public_key == node_addr);
+    assert!(Address::generate(&mut keys).public_key != node_addr);
 
+    let other = mine_deterministically(b"roses");
+    assert!(other.chain[0].hash() == first.chain[0].hash());
+    assert!(other.chain[1].hash() != first.chain[1].hash());
+}
+
+#[test]
+fn test_node_status() {
+    let mut blockchain = Blockchain::new();
+    blockchain.spec.network = String::from("<test>");
+    blockchain.mine();
+
+    let node_status = status::NodeStatus::of(&blockchain);
+    assert!(node_status.height == 1);
+    assert!(node_status.tip == blockchain.chain[1].hash());
+    assert!(node_status.mempool == 0);
+    assert!(node_status.balance.confirmed == 5);
+
+    let json = serde_json::to_value(&node_status).unwrap();
+    assert!(json["mining"] == serde_json::Value::String(String::from("on_demand")));
+    assert!(json["address"] == serde_json::Value::String(address::encode(&node_status.address)));
+
+    let html = node_status.to_html();
+    assert!(html.contains(&hex::encode(&node_status.tip)));
+    assert!(html.contains("&lt;test&gt;") && !html.contains("<test>"));
+}
+
+// A client signs the payload it's handed with its own key, and the node accepts the
+// result without ever seeing the key
+#[test]
+fn test_client_side_signing() {
+    let (public_key, private_key) = sign::keypair_from_seed(&sign::Seed([3; 32]));
+    let recipient_addr = Address::new().public_key;
+
+    let mut transaction = Transaction::new(Some(public_key), recipient_addr, 0, 0);
+    let payload = transaction.signable_payload();
+    assert!(transaction.verify_digest() == Err(TxError::BadSignature));
+
+    transaction.signed_digest = Some(sign::sign(&payload, &private_key));
+    assert!(transaction.verify_digest().is_ok());
+
+    // The payload is the same as what the node signs with its own keys
+    let mut signed_by_node = Transaction::new(Some(public_key), recipient_addr, 0, 0);
+    signed_by_node.sign(&private_key);
+    assert!(signed_by_node == transaction);
+
+    // The signature covers the value, the recipient and the sequence number
+    let mut tampered = transaction.clone();
+    tampered.value = 1;
+    assert!(tampered.verify_digest() == Err(TxError::BadSignature));
+    tampered.value = 0;
+    tampered.recipient_addr = public_key;
+    assert!(tampered.verify_digest() == Err(TxError::BadSignature));
+    tampered.recipient_addr = recipient_addr;
+    tampered.sequence = 1;
+    assert!(tampered.verify_digest() == Err(TxError::BadSignature));
+
+    let mut blockchain = Blockchain::new();
+    assert!(blockchain.append_transaction(transaction).is_ok());
+}
+
+// Transactions and blocks that are turned down are answered with a status fitting the
+// reason, and a body naming it
+#[test]
+fn test_rejection_responses() {
+    let mut miner = Blockchain::new();
+    let stale = miner.mine();
+    let chain = Arc::new(RwLock::new(miner.clone()));
+
+    // The node hasn't seen the block the tampered one is a copy of
+    let mut tampered = miner.mine();
+    tampered.body.coinbase_transaction.value += 1;
+
+    let rocket = full_node(rocket::ignite(), chain, Wallet::generate(), ApiAuth(None));
+    let client = Client::new(rocket).unwrap();
+
+    let post = |path: &str, body: String| {
+        let mut response = client
+            .post(path)
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+        let error: serde_json::Value =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+
+        (
+            response.status(),
+            error["error"].as_str().unwrap().to_string(),
+        )
+    };
+
+    let sender = Address::new();
+    let mut forged = sender.new_transaction(1, Address::new().public_key, 0);
+    forged.value = 2;
+    let coinbase = Transaction::create_coinbase_transaction(sender.public_key, 5, 1);
+    let unfunded = sender.new_transaction(1, Address::new().public_key, 0);
+
+    let submit = |transaction: &Transaction| {
+        post(
+            "/transactions/submit",
+            serde_json::to_string(transaction).unwrap(),
+        )
+    };
+    assert!(submit(&forged) == (Status::BadRequest, String::from("invalid_signature")));
+    assert!(submit(&coinbase) == (Status::BadRequest, String::from("coinbase")));
+    assert!(
+        submit(&unfunded)
+            == (
+                Status::UnprocessableEntity,
+                String::from("insufficient_funds")
+            )
+    );
+
+    let announce =
+        |block: &Block| post("/network/blocks/new", serde_json::to_string(block).unwrap());
+    assert!(announce(&tampered) == (Status::BadRequest, String::from("invalid_merkle_root")));
+
+    // A block that doesn't build on the tip conflicts with the chain
+    let mut fork = stale.clone();
+    fork.header.timestamp = fork.header.timestamp + Duration::seconds(1);
+    fork.header.nonce = Blockchain::find_nonce(&fork.header, miner.spec.difficulty);
+    assert!(announce(&fork) == (Status::Conflict, String::from("not_on_tip")));
+}
+
+fn get_balance(client: &Client, addr: &str) -> Balance {
+    let mut response = client
+        .get(format!("/addresses/{}/balance", addr))
+        .dispatch();
+    assert!(response.status() == Status::Ok);
+
+    serde_json::from_str(&response.body_string().unwrap()).unwrap()
+}
+
+// Spending through the api takes the node's balance from the ledger, so mined rewards
+// can be spent and the same tulips can't be spent twice
+#[test]
+fn test_spending_through_api() {
+    let wallet = Wallet::generate();
+    let node_addr = address::encode(&wallet.address(0).public_key);
+    let recipient_addr = address::encode(&Address::new().public_key);
+
+    let mut chain = Blockchain::new();
+    chain.address = Some(wallet.address(0));
+    let chain = Arc::new(RwLock::new(chain));
+    let rocket = full_node(rocket::ignite(), chain.clone(), wallet, ApiAuth(None));
+    let client = Client::new(rocket).unwrap();
+
+    let pay = |value: u32| {
+        let mut response = client
+            .post("/transactions/new")
+            .header(ContentType::JSON)
+            .body(format!(
+                "{{\"recipient_addr\": \"{}\", \"value\": {}}}",
+                recipient_addr, value
+            ))
+            .dispatch();
+        let body = response.body_string().unwrap();
+        if response.status() == Status::Ok {
+            return Some(serde_json::from_str::<Transaction>(&body).unwrap());
+        }
+
+        // Payments the node can't afford are unprocessable
+        assert!(response.status() == Status::UnprocessableEntity);
+        let error: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert!(error["error"] == "insufficient_funds");
+        None
+    };
+
+    // Nothing to spend before the first block is mined
+    assert!(pay(1).is_none());
+
+    assert!(client.get("/mine").dispatch().status() == Status::Ok);
+    let balance = get_balance(&client, &node_addr);
+    assert!(balance.confirmed == 5 && balance.available == 5);
+
+    assert!(pay(3).is_some());
+    let balance = get_balance(&client, &node_addr);
+    assert!(balance.confirmed == 5 && balance.pending == 2 && balance.available == 2);
+    assert!(get_balance(&client, &recipient_addr).pending == 3);
+
+    // The pending payment is taken into account
+    assert!(pay(3).is_none());
+    assert!(chain.read().unwrap().pending_transactions.len() == 1);
+
+    assert!(client.get("/mine").dispatch().status() == Status::Ok);
+    let balance = get_balance(&client, &node_addr);
+    assert!(balance.confirmed == 7 && balance.available == 7);
+    assert!(get_balance(&client, &recipient_addr).confirmed == 3);
+
+    let response = client.get("/addresses/not-an-address/balance").dispatch();
+    assert!(response.status() == Status::BadRequest);
+}
+
+// A signed transaction is accepted once. Resubmitting it, whether it's still pending or
+// already confirmed, doesn't spend the sender's tulips again.
+#[test]
+fn test_replayed_transaction() {
+    let mut miner = Blockchain::new();
+    let sender = miner.address.clone().unwrap();
+    miner.mine();
+    let recipient_addr = Address::new().public_key;
+    let chain = Arc::new(RwLock::new(miner));
+
+    let rocket = full_node(
+        rocket::ignite(),
+        chain.clone(),
+        Wallet::generate(),
+        ApiAuth(None),
+    );
+    let client = Client::new(rocket).unwrap();
+    let post = |path: &str, transaction: &Transaction| {
+        client
+            .post(path)
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(transaction).unwrap())
+            .dispatch()
+            .status()
+    };
+
+    let transaction = sender.new_transaction(2, recipient_addr, 0);
+    assert!(post("/transactions/submit", &transaction) == Status::Ok);
+    assert!(post("/transactions/submit", &transaction) == Status::Conflict);
+    assert!(chain.read().unwrap().pending_transactions.len() == 1);
+
+    // Peers relay it back to us, which isn't held against them
+    assert!(post("/network/transactions/new", &transaction) == Status::Ok);
+    assert!(chain.read().unwrap().pending_transactions.len() == 1);
+    assert!(
+        chain
+            .read()
+            .unwrap()
+            .metrics
+            .transactions_rejected
+            .get("bad_sequence")
+            == Some(&1)
+    );
+
+    chain.write().unwrap().mine();
+    assert!(post("/transactions/submit", &transaction) == Status::Conflict);
+    assert!(post("/network/transactions/new", &transaction) == Status::Conflict);
+
+    let chain = chain.read().unwrap();
+    assert!(chain.pending_transactions.is_empty());
+    assert!(chain.balance(&recipient_addr).confirmed == 2);
+    let balance = chain.balance(&sender.public_key);
+    assert!(balance.confirmed == 8 && balance.next_sequence == 1);
+
+    // Skipping sequence numbers isn't allowed either
+    let ahead = sender.new_transaction(1, recipient_addr, 2);
+    assert!(
+        chain.clone().append_transaction(ahead)
+            == Err(TxError::BadSequence {
+                expected: 1,
+                sequence: 2,
+            })
+    );
+}
+
+// Mines a block on the chain's tip holding the given transactions, without adding it
+fn mine_on(chain: &Blockchain, transactions: Vec<Transaction>, coinbase: Transaction) -> Block {
+    let tip = chain.chain.last().unwrap();
+    let mut block = Block::new(
+        chain.chain.len(),
+        chain.clock.now(),
+        transactions,
+        Some(tip.hash()),
+        coinbase,
+        0,
+    );
+    block.header.nonce = Blockchain::find_nonce(&block.header, chain.spec.difficulty);
+
+    block
+}
+
+// Blocks from peers can't pay out more than the reward, spend tulips their senders
+// don't have, or replay transactions
+#[test]
+fn test_block_ledger_validation() {
+    let mut chain = Blockchain::new();
+    let miner = chain.address.clone().unwrap();
+    chain.mine();
+
+    let recipient_addr = Address::new().public_key;
+    let reward = chain.spec.reward;
+    let coinbase = Transaction::create_coinbase_transaction(recipient_addr, reward, 2);
+
+    let minted = Transaction::create_coinbase_transaction(recipient_addr, reward + 1, 2);
+    let block = mine_on(&chain, vec![], minted);
+    assert!(chain.accept_block(block) == Err(BlockError::BadCoinbase { height: 2 }));
+
+    // The coinbase has to carry the block's height, so that its id is unique
+    let reused = Transaction::create_coinbase_transaction(recipient_addr, reward, 1);
+    let block = mine_on(&chain, vec![], reused);
+    assert!(chain.accept_block(block) == Err(BlockError::BadCoinbase { height: 2 }));
+
+    let block = mine_on(&chain, vec![coinbase.clone()], coinbase.clone());
+    assert!(chain.accept_block(block) == Err(BlockError::BadCoinbase { height: 2 }));
+
+    let overdraft = vec![
+        miner.new_transaction(3, recipient_addr, 0),
+        miner.new_transaction(3, recipient_addr, 1),
+    ];
+    let block = mine_on(&chain, overdraft, coinbase.clone());
+    assert!(
+        chain.accept_block(block)
+            == Err(BlockError::BadTransaction {
+                height: 2,
+                error: TxError::InsufficientFunds {
+                    available: 2,
+                    value: 3,
+                },
+            })
+    );
+
+    let payment = miner.new_transaction(1, recipient_addr, 0);
+    let block = mine_on(
+        &chain,
+        vec![payment.clone(), payment.clone()],
+        coinbase.clone(),
+    );
+    assert!(
+        chain.accept_block(block)
+            == Err(BlockError::BadTransaction {
+                height: 2,
+                error: TxError::BadSequence {
+                    expected: 1,
+                    sequence: 0,
+                },
+            })
+    );
+
+    // None of the blocks that were turned down left a trace in the ledger
+    assert!(chain.chain.len() == 2);
+    assert!(chain.balance(&recipient_addr).confirmed == 0);
+    assert!(chain.balance(&miner.public_key).confirmed == 5);
+
+    let block = mine_on(&chain, vec![payment.clone()], coinbase.clone());
+    assert!(chain.accept_block(block).is_ok());
+    assert!(chain.balance(&recipient_addr).confirmed == 6);
+    assert!(chain.balance(&miner.public_key).confirmed == 4);
+
+    // A confirmed transaction can't be replayed in a later block
+    let block = mine_on(&chain, vec![payment], coinbase.clone());
+    assert!(
+        chain.accept_block(block)
+            == Err(BlockError::BadTransaction {
+                height: 3,
+                error: TxError::BadSequence {
+                    expected: 1,
+                    sequence: 0,
+                },
+            })
+    );
+
+    // A chain holding an overdraft doesn't validate either
+    let overdraft = miner.new_transaction(10, recipient_addr, 1);
+    let mut forged = chain.clone();
+    forged
+        .chain
+        .push(mine_on(&chain, vec![overdraft], coinbase));
+    assert!(
+        forged.validate_chain()
+            == Err(BlockError::BadTransaction {
+                height: 3,
+                error: TxError::InsufficientFunds {
+                    available: 4,
+                    value: 10,
+                },
+            })
+    );
+    assert!(chain.validate_chain().is_ok());
+}
+
+#[test]
+fn test_transaction_lookup() {
+    let mut blockchain = Blockchain::new();
+    let node_addr = blockchain.address.clone().unwrap();
+    blockchain.mine();
+
+    let coinbase = blockchain.chain[1].body.coinbase_transaction.clone();
+    let entry = blockchain.find_transaction(&coinbase.id()).unwrap();
+    assert!(entry.height == Some(1) && entry.transaction == coinbase);
+
+    let transaction = node_addr.new_transaction(2, Address::new().public_key, 0);
+    assert!(blockchain.append_transaction(transaction.clone()).is_ok());
+    let entry = blockchain.find_transaction(&transaction.id()).unwrap();
+    assert!(entry.height.is_none() && entry.txid == transaction.id());
+
+    assert!(blockchain.find_transaction(&[0; 32]).is_none());
+}
+
+#[test]
+fn test_node_config() {
+    let config = NodeConfig::parse(include_str!("../node.example.toml")).unwrap();
+    assert!(config.validate().is_ok());
+    assert!(config.port == 8000 && config.p2p_port == Some(9000));
+    assert!(config.mining.enabled && config.mining.threads == 2);
+    assert!(config.api.token == Some(String::from("change me")));
+
+    // Settings that are left out take their defaults
+    let config = NodeConfig::parse("port = 8001").unwrap();
+    assert!(config.port == 8001 && config.address == NodeConfig::default().address);
+    assert!(config.validate().is_ok());
+
+    // Typos in setting names are caught rather than ignored
+    match NodeConfig::parse("prot = 8001") {
+        Err(ConfigError::Parse(_)) => {}
+        _ => panic!("expected a parse error"),
+    }
+
+    let mut config = NodeConfig::default();
+    config.mining.threads = 0;
+    match config.validate() {
+        Err(ConfigError::Threads) => {}
+        _ => panic!("expected an error about the threads"),
+    }
+
+    let mut config = NodeConfig::default();
+    config.mining.reward_addr = Some(String::from("not an address"));
+    match config.validate() {
+        Err(ConfigError::RewardAddress(AddressError::Encoding)) => {}
+        _ => panic!("expected an error about the reward address"),
+    }
+
+    let mut config = NodeConfig::default();
+    config.log_level = String::from("loud");
+    match config.validate() {
+        Err(ConfigError::LogLevel(_)) => {}
+        _ => panic!("expected an error about the log level"),
+    }
+
+    let mut config = NodeConfig::default();
+    config.seeds = vec![String::from("localhost")];
+    match config.validate() {
+        Err(ConfigError::Seed(_)) => {}
+        _ => panic!("expected an error about the seed"),
+    }
+
+    let mut config = NodeConfig::default();
+    config.network = Some(String::from("testnet"));
+    match config.check_network(&ChainSpec::dev()) {
+        Err(ConfigError::Network { .. }) => {}
+        _ => panic!("expected an error about the network"),
+    }
+}
+
+// With a token configured, the wallet and miner endpoints turn away requests that
+// don't carry it
+#[test]
+fn test_api_auth() {
+    let wallet = Wallet::generate();
+    let mut chain = Blockchain::new();
+    chain.address = Some(wallet.address(0));
+    let chain = Arc::new(RwLock::new(chain));
+
+    let token = Some(String::from("secret"));
+    let rocket = full_node(rocket::ignite(), chain.clone(), wallet, ApiAuth(token));
+    let client = Client::new(rocket).unwrap();
+
+    assert!(client.get("/mine").dispatch().status() == Status::Unauthorized);
+    let response = client
+        .get("/mine")
+        .header(Header::new("Authorization", "Bearer wrong"))
+        .dispatch();
+    assert!(response.status() == Status::Unauthorized);
+    let response = client
+        .get("/mine")
+        .header(Header::new("Authorization", "Bearer secret"))
+        .dispatch();
+    assert!(response.status() == Status::Ok);
+    assert!(chain.read().unwrap().chain.len() == 2);
+
+    // Reading the chain stays open
+    assert!(client.get("/chain/tip").dispatch().status() == Status::Ok);
+    assert!(client.get("/").dispatch().status() == Status::Ok);
+}
+
+#[test]
+fn test_reward_address() {
+    let mut blockchain = Blockchain::new();
+    assert!(blockchain.mining == MiningState::OnDemand);
+
+    let reward_addr = Address::new().public_key;
+    blockchain.reward_addr = Some(reward_addr);
+    blockchain.mine();
+    assert!(blockchain.balance(&reward_addr).confirmed == blockchain.spec.reward);
+}
+
+#[test]
+fn test_log_filter() {
+    let filter = LogFilter::parse("warn,tulipchain::sync=debug,tulipchain=info").unwrap();
+    assert!(filter.level("rocket") == LevelFilter::Warn);
+    assert!(filter.level("tulipchain::blockchain") == LevelFilter::Info);
+    assert!(filter.level("tulipchain::sync") == LevelFilter::Debug);
+
+    // Modules only match whole path segments
+    assert!(filter.level("tulipchain_cli") == LevelFilter::Warn);
+
+    assert!(LogFilter::parse("").unwrap().level("tulipchain") == LevelFilter::Info);
+    assert!(LogFilter::parse("tulipchain=loud") == Err(LogError::Level(String::from("loud"))));
+
+    assert!("json".parse::<LogFormat>() == Ok(LogFormat::Json));
+    assert!("xml".parse::<LogFormat>().is_err());
+
+    let mut config = NodeConfig::default();
+    config.log_format = String::from("xml");
+    match config.validate() {
+        Err(ConfigError::LogFormat(_)) => {}
+        _ => panic!("expected an error about the log format"),
+    }
+}
+
+#[test]
+fn test_json_log_record() {
+    let fields = vec![
+        ("height", String::from("3")),
+        ("hash", String::from("00ab")),
+        ("reason", String::from("bad_pow")),
+        ("error", String::from("the block doesn't meet the difficulty")),
+    ];
+    let line = logging::format_record(
+        Level::Warn,
+        "tulipchain::blockchain",
+        &format_args!("Rejected block"),
+        &fields,
+        LogFormat::Json,
+    );
+
+    let record: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert!(record["level"] == "WARN");
+    assert!(record["target"] == "tulipchain::blockchain");
+    assert!(record["message"] == "Rejected block");
+    assert!(record["time"].is_string());
+    assert!(record["height"] == "3");
+    assert!(record["hash"] == "00ab");
+    assert!(record["reason"] == "bad_pow");
+
+    // Text records keep the fields in the message, quoting values with spaces
+    let line = logging::format_record(
+        Level::Warn,
+        "tulipchain::blockchain",
+        &format_args!("Rejected block"),
+        &fields,
+        LogFormat::Text,
+    );
+    assert!(line.ends_with(
+        "Rejected block: height=3 hash=00ab reason=bad_pow \
+         error=\"the block doesn't meet the difficulty\""
+    ));
+
+    let line = logging::format_record(
+        Level::Info,
+        "tulipchain::wire",
+        &format_args!("Listening"),
+        &[],
+        LogFormat::Text,
+    );
+    assert!(line.ends_with("tulipchain::wire: Listening"));
+}
+
+#[test]
+fn test_metrics() {
+    let wallet = Wallet::generate();
+    let mut chain = Blockchain::new();
+    chain.address = Some(wallet.address(0));
+
+    // A transaction without funds, and a block that doesn't extend the tip
+    let sender = Address::new();
+    let transaction = sender.new_transaction(10, Address::new().public_key, 0);
+    assert!(
+        chain.append_transaction(transaction)
+            == Err(TxError::InsufficientFunds {
+                available: 0,
+                value: 10,
+            })
+    );
+    let mut block = chain.mine();
+    block.header.ind += 1;
+    block.header.nonce = Blockchain::find_nonce(&block.header, chain.spec.difficulty);
+    assert!(chain.accept_block(block) == Err(BlockError::BadPrevHash { height: 2 }));
+
+    assert!(chain.metrics.blocks_accepted == 1);
+    assert!(chain.metrics.blocks_rejected.get("not_on_tip") == Some(&1));
+    assert!(
+        chain
+            .metrics
+            .transactions_rejected
+            .get("insufficient_funds")
+            == Some(&1)
+    );
+    assert!(chain.metrics.hashes > 0);
+
+    let rendered = metrics::render(&chain, &RequestMetrics::default());
+    assert!(rendered.contains("tulipchain_chain_height 1\n"));
+    assert!(rendered.contains("tulipchain_blocks_rejected_total{reason=\"not_on_tip\"} 1\n"));
+    assert!(rendered
+        .contains("tulipchain_transactions_rejected_total{reason=\"insufficient_funds\"} 1\n"));
+
+    let chain = Arc::new(RwLock::new(chain));
+    let client = Client::new(full_node(rocket::ignite(), chain, wallet, ApiAuth(None))).unwrap();
+    assert!(client.get("/chain/tip").dispatch().status() == Status::Ok);
+
+    let mut response = client.get("/metrics").dispatch();
+    assert!(response.status() == Status::Ok);
+    let body = response.body_string().unwrap();
+    assert!(body.contains("tulipchain_mempool_transactions 0\n"));
+    assert!(body.contains(
+        "tulipchain_http_request_duration_seconds_count{handler=\"GET /chain/tip\"} 1\n"
+    ));
+}
+
+// A branch downloaded without holding on to the chain is only applied if it still
+// builds on the chain and is longer than it
+#[test]
+fn test_apply_branch() {
+    let mut chain = Blockchain::new();
+    chain.mine();
+    chain.mine();
+
+    let mut fork = Blockchain::new();
+    fork.mine();
+    fork.mine();
+    fork.mine();
+
+    let branch = Branch {
+        fork_height: 1,
+        blocks: fork.blocks(1, 2),
+    };
+    assert!(sync::apply_branch(&mut chain, branch).unwrap() == 0);
+
+    // The branch's first block doesn't build on the chain's block below it
+    let branch = Branch {
+        fork_height: 2,
+        blocks: fork.blocks(2, 2),
+    };
+    assert!(sync::apply_branch(&mut chain, branch).unwrap() == 0);
+
+    let branch = Branch {
+        fork_height: 1,
+        blocks: fork.blocks(1, 3),
+    };
+    assert!(sync::apply_branch(&mut chain, branch).unwrap() == 3);
+    assert!(chain.chain == fork.chain);
+
+    // Blocks that were accepted in the meantime are skipped
+    fork.mine();
+    fork.mine();
+    assert!(chain.accept_block(fork.chain[4].clone()).is_ok());
+    let branch = Branch {
+        fork_height: 3,
+        blocks: fork.blocks(3, 3),
+    };
+    assert!(sync::apply_branch(&mut chain, branch).unwrap() == 1);
+    assert!(chain.chain == fork.chain);
+    assert!(chain.metrics.reorg_depths.count() == 1);
+}
+
+#[test]
+fn test_reorg_metrics() {
+    let mut chain = Blockchain::new();
+    chain.mine();
+    chain.mine();
+
+    let mut fork = Blockchain::new();
+    fork.mine();
+    fork.mine();
+    fork.mine();
+
+    assert!(chain.reorganize(1, fork.blocks(1, 3)).is_ok());
+    assert!(chain.metrics.reorg_depths.count() == 1);
+    assert!(chain.metrics.reorg_depths.sum() == 2.0);
+
+    // Catching up with the network isn't a reorganization
+    fork.mine();
+    assert!(chain.reorganize(4, fork.blocks(4, 1)).is_ok());
+    assert!(chain.metrics.reorg_depths.count() == 1);
+}
+
+#[test]
+fn test_join_network() {
+    let network = Network::start(2);
+    network.mine(0);
+    network.mine(0);
+
+    // The joining node downloads the chain and both become peers
+    network.join(1, 0);
+    assert!(network.nodes[1].tip() == network.nodes[0].tip());
+    assert!(network.nodes[0]
+        .chain
+        .read()
+        .unwrap()
+        .peers
+        .contains(&network.nodes[1].url));
+    assert!(network.nodes[1]
+        .chain
+        .read()
+        .unwrap()
+        .peers
+        .contains(&network.nodes[0].url));
+
+    // Blocks are gossiped from then on
+    network.mine(1);
+    assert!(network.assert_converged() == 3);
+}
+
+#[test]
+fn test_fork_resolution() {
+    let mut network = Network::connected(3);
+    network.mine(0);
+    network.assert_converged();
+
+    // Both sides of the partition keep mining, one of them faster
+    network.partition(&[2]);
+    network.mine(2);
+    network.mine(0);
+    network.mine(0);
+    network.wait_until("node 1 follows node 0", |network| {
+        network.nodes[1].tip() == network.nodes[0].tip()
+    });
+    assert!(network.nodes[0].height() == 3);
+    assert!(network.nodes[2].height() == 2);
+
+    // Once the partition heals, the next block on the longer chain makes the shorter
+    // side switch over to it
+    network.heal();
+    assert!(network.nodes[2].height() == 2);
+    network.mine(0);
+    assert!(network.assert_converged() == 4);
+    assert!(
+        network.nodes[2]
+            .chain
+            .read()
+            .unwrap()
+            .metrics
+            .reorg_depths
+            .count()
+            == 1
+    );
+}
+
+// Three honest miners sharing what's left of the hash power
+fn with_honest_miners(first: Miner) -> Vec<Miner> {
+    let power = (1.0 - first.power) / 3.0;
+    vec![
+        first,
+        Miner::honest(power),
+        Miner::honest(power),
+        Miner::honest(power),
+    ]
+}
+
+#[test]
+fn test_simulation_is_reproducible() {
+    let config = SimConfig::new(7, with_honest_miners(Miner::honest(0.25)));
+    let mut first = Simulation::new(config.clone());
+    let mut second = Simulation::new(config.clone());
+
+    let report = first.run(20 * config.block_interval);
+    assert!(report.height > 0);
+    assert!(second.run(20 * config.block_interval) == report);
+    assert!(first.chain(2).chain == second.chain(2).chain);
+
+    let mut other = Simulation::new(SimConfig::new(8, config.miners.clone()));
+    assert!(other.run(20 * config.block_interval).tip != report.tip);
+}
+
+#[test]
+fn test_simulated_latency_causes_orphans() {
+    let mut config = SimConfig::new(1, with_honest_miners(Miner::honest(0.25)));
+    config.block_interval = 10_000;
+    config.latency = 10;
+    config.jitter = 0;
+    let fast = Simulation::new(config.clone()).run(1000 * config.block_interval);
+
+    config.latency = 2_000;
+    config.jitter = 1_000;
+    let slow = Simulation::new(config.clone()).run(1000 * config.block_interval);
+
+    assert!(slow.orphan_rate() > 0.05);
+    assert!(slow.orphan_rate() > fast.orphan_rate());
+    assert!(slow.reorgs > fast.reorgs);
+}
+
+#[test]
+fn test_simulated_partition() {
+    let mut config = SimConfig::new(3, with_honest_miners(Miner::honest(0.25)));
+    config.block_interval = 10_000;
+    config.partitions = vec![Partition {
+        from: 0,
+        until: 200_000,
+        side: vec![0, 1],
+    }];
+
+    let mut simulation = Simulation::new(config);
+    let report = simulation.run(400_000);
+
+    // One side's blocks are thrown away once the partition heals
+    assert!(report.deepest_reorg >= 2);
+
+    // Apart from the last few blocks, which may still be on their way, the nodes agree
+    let height = (0..4)
+        .map(|node| simulation.chain(node).chain.len())
+        .min()
+        .unwrap()
+        - 3;
+    let block = simulation.chain(0).chain[height].clone();
+    for node in 1..4 {
+        assert!(simulation.chain(node).chain[height] == block);
+    }
+}
+
+#[test]
+fn test_simulated_selfish_mining() {
+    let mut config = SimConfig::new(5, with_honest_miners(Miner::honest(0.45)));
+    config.block_interval = 10_000;
+    let honest = Simulation::new(config.clone()).run(1000 * config.block_interval);
+    assert!(honest.share(0) < 0.52);
+
+    // Withholding blocks earns more than the miner's share of the hash power
+    config.miners = with_honest_miners(Miner::selfish(0.45));
+    let selfish = Simulation::new(config.clone()).run(1000 * config.block_interval);
+    assert!(selfish.share(0) > 0.5);
+    assert!(selfish.orphan_rate() > honest.orphan_rate());
+
+    // With a majority of the hash power, the miner ends up with nearly every block
+    config.miners = with_honest_miners(Miner::selfish(0.6));
+    let majority = Simulation::new(config.clone()).run(1000 * config.block_interval);
+    assert!(majority.share(0) > 0.8);
+}
+
+// Keys are derived from seeds in the property tests, so that failing cases shrink to
+// something readable
+fn arb_transaction() -> BoxedStrategy<Transaction> {
+    (
+        prop::array::uniform32(any::<u8>()),
+        prop::array::uniform32(any::<u8>()),
+        any::<u32>(),
+        any::<bool>(),
+    )
+        .prop_map(|(sender, recipient, value, coinbase)| {
+            let recipient_addr = Address::from_seed(&sign::Seed(recipient)).public_key;
+            if coinbase {
+                Transaction::create_coinbase_transaction(recipient_addr, value, 1)
+            } else {
+                Address::from_seed(&sign::Seed(sender)).new_transaction(value, recipient_addr, 0)
+            }
+        })
+        .boxed()
+}
+
+fn arb_block() -> BoxedStrategy<Block> {
+    (
+        any::<usize>(),
+        0i64..4_102_444_800,
+        prop::collection::vec(arb_transaction(), 0..8),
+        prop::option::of(prop::collection::vec(any::<u8>(), 32)),
+        arb_transaction(),
+        any::<u32>(),
+    )
+        .prop_map(
+            |(ind, timestamp, transactions, previous_hash, coinbase, nonce)| {
+                Block::new(
+                    ind,
+                    Utc.timestamp(timestamp, 0),
+                    transactions,
+                    previous_hash,
+                    coinbase,
+                    nonce,
+                )
+            },
+        )
+        .boxed()
+}
+
+proptest! {
+    #[test]
+    fn test_signatures_cover_the_transaction(
+        sender in prop::array::uniform32(any::<u8>()),
+        recipient in prop::array::uniform32(any::<u8>()),
+        value in any::<u32>(),
+        tampered in any::<u32>()
+    ) {
+        let recipient_addr = Address::from_seed(&sign::Seed(recipient)).public_key;
+        let mut transaction =
+            Address::from_seed(&sign::Seed(sender)).new_transaction(value, recipient_addr, 0);
+        prop_assert!(transaction.verify_digest().is_ok());
+
+        prop_assume!(tampered != value);
+        transaction.value = tampered;
+        prop_assert!(transaction.verify_digest() == Err(TxError::BadSignature));
+    }
+
+    #[test]
+    fn test_transaction_round_trip(transaction in arb_transaction()) {
+        let json = serde_json::to_string(&transaction).unwrap();
+        let received: Transaction = serde_json::from_str(&json).unwrap();
+
+        prop_assert!(received.id() == transaction.id());
+        prop_assert_eq!(received, transaction);
+    }
+
+    #[test]
+    fn test_block_round_trip(block in arb_block()) {
+        prop_assert_eq!(Block::decode(&block.encode()).unwrap(), block.clone());
+
+        let json = serde_json::to_string(&block).unwrap();
+        prop_assert_eq!(serde_json::from_str::<Block>(&json).unwrap(), block.clone());
+
+        // Only a coinbase that has a sender, doesn't pay the reward or doesn't carry the
+        // block's height keeps a block from validating, and transactions without a
+        // sender outside of the genesis block
+        let coinbase = &block.body.coinbase_transaction;
+        let senderless = block
+            .body
+            .transactions
+            .iter()
+            .any(|transaction| transaction.sender_addr.is_none());
+        let valid = coinbase.sender_addr.is_none()
+            && coinbase.sequence == block.header.ind as u64
+            && (!senderless || block.header.ind == 0);
+        prop_assert!(block.validate_body(coinbase.value).is_ok() == valid);
+        prop_assert!(block.validate_body(coinbase.value.wrapping_add(1)).is_err());
+    }
+
+    // What peers send may be anything, which has to be turned down without panicking
+    #[test]
+    fn test_decoding_garbage(bytes in prop::collection::vec(any::<u8>(), 0..1024)) {
+        if let Ok(block) = Block::decode(&bytes) {
+            let _ = block.validate_body(ChainSpec::dev().reward);
+        }
+
+        if let Ok(transaction) = serde_json::from_slice::<Transaction>(&bytes) {
+            let _ = transaction.verify_digest();
+        }
+
+        if let Ok(received) = serde_json::from_slice::<Blockchain>(&bytes) {
+            let _ = Blockchain::from_blocks(ChainSpec::dev(), received.chain).validate_chain();
+        }
+    }
+}
+
+// A served chain with any one byte changed is either turned down or still valid, but
+// never panics the node
+#[test]
+fn test_corrupted_chain() {
+    let mut blockchain = Blockchain::new();
+    let transaction =
+        blockchain
+            .address
+            .clone()
+            .unwrap()
+            .new_transaction(0, Address::new().public_key, 0);
+    blockchain.append_transaction(transaction).unwrap();
+    blockchain.mine();
+    let served = serde_json::to_vec(&blockchain).unwrap();
+
+    let mut runner = TestRunner::default();
+    runner
+        .run(&(any::<usize>(), any::<u8>()), |(position, byte)| {
+            let mut bytes = served.clone();
+            let position = position % bytes.len();
+            bytes[position] = byte;
+
+            if let Ok(received) = serde_json::from_slice::<Blockchain>(&bytes) {
+                let received = Blockchain::from_blocks(ChainSpec::dev(), received.chain);
+                let _ = received.validate_chain();
+            }
+
+            Ok(())
+        })
+        .unwrap();
 }
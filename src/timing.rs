@@ -0,0 +1,40 @@
+use tulipchain::metrics::SharedRequestMetrics;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Data, Request, Response};
+use std::cell::Cell;
+use std::time::Instant;
+
+// Requests are handled from start to finish on a single thread, which is where the
+// time the current one started at is kept
+thread_local!(static STARTED: Cell<Option<Instant>> = Cell::new(None));
+
+// Fairing that records how long the handlers take, by route
+pub struct RequestTimer(pub SharedRequestMetrics);
+
+impl Fairing for RequestTimer {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request timer",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, _request: &mut Request, _data: &Data) {
+        STARTED.with(|started| started.set(Some(Instant::now())));
+    }
+
+    fn on_response(&self, request: &Request, _response: &mut Response) {
+        let started = match STARTED.with(|started| started.take()) {
+            Some(started) => started,
+            None => return,
+        };
+
+        let handler = match request.route() {
+            Some(route) => format!("{} {}", route.method, route.uri.path()),
+            None => String::from("unmatched"),
+        };
+
+        self.0.lock().unwrap().observe(&handler, started.elapsed());
+    }
+}
@@ -1,21 +1,97 @@
 use sodiumoxide::crypto::{hash, sign};
 use bincode::serialize;
-use super::{PrivateKey, PublicKey, SignedDigest, Tulips};
+use super::{address, hex};
+use super::{Digest, PrivateKey, PublicKey, SignedDigest, Tulips};
+
+use std::fmt;
+
+// Why a transaction is turned down
+#[derive(Debug, Clone, PartialEq)]
+pub enum TxError {
+    // The signature is missing, or wasn't made by the sender over this transaction
+    BadSignature,
+    // The sender can't afford the transaction, counting its pending payments
+    InsufficientFunds { available: Tulips, value: Tulips },
+    // The transaction has no sender. Only the coinbase transaction of a block and the
+    // genesis allocations go without one.
+    UnknownCoinbase,
+    // The sequence number isn't the sender's next one: it's been used already, by a
+    // confirmed or a pending transaction, or numbers were skipped
+    BadSequence { expected: u64, sequence: u64 },
+}
+
+impl TxError {
+    // Short name of the error, as used in metrics and error responses
+    pub fn reason(&self) -> &'static str {
+        match *self {
+            TxError::BadSignature => "invalid_signature",
+            TxError::InsufficientFunds { .. } => "insufficient_funds",
+            TxError::UnknownCoinbase => "coinbase",
+            TxError::BadSequence { .. } => "bad_sequence",
+        }
+    }
+}
+
+impl fmt::Display for TxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TxError::BadSignature => write!(f, "the transaction isn't signed by its sender"),
+            TxError::InsufficientFunds { available, value } => write!(
+                f,
+                "the sender has {} tulips available, but the transaction is worth {}",
+                available, value
+            ),
+            TxError::UnknownCoinbase => write!(f, "the transaction has no sender"),
+            TxError::BadSequence { expected, sequence } => write!(
+                f,
+                "the transaction has sequence number {}, but the sender's next one is {}",
+                sequence, expected
+            ),
+        }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct PartialTransaction {
+    #[serde(with = "address")]
     pub recipient_addr: PublicKey,
     pub value: Tulips,
 }
 
+// A payment that a client wants to sign on its own, without handing its key to the node
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct UnsignedTransaction {
+    #[serde(with = "address")]
+    pub sender_addr: PublicKey,
+    #[serde(with = "address")]
+    pub recipient_addr: PublicKey,
+    pub value: Tulips,
+    pub sequence: u64,
+}
+
+// What a client signs: the payload is to be signed in ed25519's combined mode, and the
+// signed message goes into the transaction's signed_digest
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SignablePayload {
+    #[serde(with = "hex")]
+    pub payload: Digest,
+    pub transaction: Transaction,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Transaction {
     // Coinbase transactions have no sender
+    #[serde(with = "address::option")]
     pub sender_addr: Option<PublicKey>,
+    #[serde(with = "address")]
     pub recipient_addr: PublicKey,
     pub value: Tulips,
 
-    #[serde(skip)]
+    // Counts the sender's transactions, starting at 0. It's signed along with the
+    // rest, so that a signed transaction can't be replayed.
+    pub sequence: u64,
+
+    #[serde(with = "hex::option")]
     pub signed_digest: Option<SignedDigest>,
 }
 
@@ -24,49 +100,88 @@ impl Transaction {
         sender_addr: Option<PublicKey>,
         recipient_addr: PublicKey,
         value: Tulips,
+        sequence: u64,
     ) -> Transaction {
         Transaction {
             sender_addr,
             recipient_addr,
             value,
+            sequence,
             signed_digest: None,
         }
     }
 
+    // Identifies the transaction. The signature is included, so a transaction has no
+    // id that can be referred to before it's been signed.
+    pub fn id(&self) -> Digest {
+        let hash::sha256::Digest(ref digest) = hash::sha256::hash(&serialize(self).unwrap());
+
+        digest.to_vec()
+    }
+
+    // Digest of everything but the signature, which is what gets signed
+    fn signable_digest(&self) -> hash::sha256::Digest {
+        let serialized = serialize(&(
+            &self.sender_addr,
+            &self.recipient_addr,
+            self.value,
+            self.sequence,
+        ))
+        .unwrap();
+
+        hash::sha256::hash(&serialized)
+    }
+
+    // The bytes a sender signs, for clients that sign transactions themselves
+    pub fn signable_payload(&self) -> Digest {
+        let hash::sha256::Digest(ref digest) = self.signable_digest();
+
+        digest.to_vec()
+    }
+
     pub fn sign(&mut self, signing_key: &PrivateKey) {
-        let serialized = serialize(self).unwrap();
-        let hash::sha256::Digest(ref digest) = hash::sha256::hash(&serialized);
+        let hash::sha256::Digest(ref digest) = self.signable_digest();
 
         // Sign the digest with the senders private key
         let signed_digest = sign::sign(digest, signing_key);
         self.signed_digest = Some(signed_digest);
     }
 
-    // Ensures that the a signature is valid for a given transaction
-    pub fn verify_digest(&self) -> bool {
-        // If the transaction is unsigned and isn't a coinbase transaction, it's invalid.
-        if let None = self.signed_digest {
-            if let Some(_) = self.sender_addr {
-                return false;
-            }
-        }
+    // Ensures that the transaction is signed by its sender. Transactions without a
+    // sender have no one to sign them, and aren't valid on their own.
+    pub fn verify_digest(&self) -> Result<(), TxError> {
+        let sender_addr = match self.sender_addr {
+            Some(ref sender_addr) => sender_addr,
+            None => return Err(TxError::UnknownCoinbase),
+        };
+
+        let signed_digest = match self.signed_digest {
+            Some(ref signed_digest) => signed_digest,
+            None => return Err(TxError::BadSignature),
+        };
 
         // Compute the digest
-        let serialized: Vec<u8> = serialize(self).unwrap();
-        let hash::sha256::Digest(ref digest) = hash::sha256::hash(&serialized);
-
-        if let Ok(verified_data) = sign::verify(
-            self.signed_digest.clone().unwrap().as_slice(),
-            &self.sender_addr.unwrap(),
-        ) {
-            return digest == &verified_data[..];
+        let hash::sha256::Digest(ref digest) = self.signable_digest();
+
+        match sign::verify(signed_digest, sender_addr) {
+            Ok(ref verified_data) if digest == &verified_data[..] => Ok(()),
+            _ => Err(TxError::BadSignature),
         }
+    }
 
-        false
+    // Checks whether the address is the sender or the recipient
+    pub fn involves(&self, addr: &PublicKey) -> bool {
+        self.sender_addr.as_ref() == Some(addr) || self.recipient_addr == *addr
     }
 
-    // Creates a coinbase transactions to pay node that found nonce for a block
-    pub fn create_coinbase_transaction(recipient_addr: PublicKey) -> Transaction {
-        Transaction::new(None, recipient_addr, 5)
+    // Creates a coinbase transactions to pay node that found nonce for a block. Coinbase
+    // transactions have no sender to count for, so their sequence number is the height
+    // of their block instead, which gives each of them an id of its own.
+    pub fn create_coinbase_transaction(
+        recipient_addr: PublicKey,
+        reward: Tulips,
+        height: usize,
+    ) -> Transaction {
+        Transaction::new(None, recipient_addr, reward, height as u64)
     }
 }
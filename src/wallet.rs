@@ -0,0 +1,280 @@
+use super::address::Address;
+use super::wordlist::WORDS;
+use super::PublicKey;
+
+use serde_json;
+use sodiumoxide::crypto::hash::sha256;
+use sodiumoxide::crypto::sign;
+use sodiumoxide::randombytes::randombytes;
+use std::cmp::max;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+// Bytes of entropy behind a seed phrase. Together with a checksum byte, a phrase
+// has one word more than that.
+const ENTROPY_BYTES: usize = 16;
+const PHRASE_WORDS: usize = ENTROPY_BYTES + 1;
+
+// Keeps the roots of different wallets' key trees apart from other uses of the entropy
+const SEED_DOMAIN: &[u8] = b"tulipchain wallet";
+
+// Receiving addresses are derived from this branch of the key tree
+const RECEIVING_BRANCH: u32 = 0;
+
+// Number of unused addresses in a row after which recovery stops looking
+pub const RECOVERY_GAP: u32 = 20;
+
+#[derive(Debug)]
+pub enum WalletError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+    // The phrase doesn't have the right number of words
+    WordCount(usize),
+    // The phrase holds a word that isn't in the word list
+    UnknownWord(String),
+    // The phrase's checksum doesn't match, most likely because of a typo
+    Checksum,
+}
+
+impl fmt::Display for WalletError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WalletError::Io(ref err) => write!(f, "couldn't access the wallet file: {}", err),
+            WalletError::Parse(ref err) => write!(f, "couldn't parse the wallet file: {}", err),
+            WalletError::WordCount(count) => {
+                write!(f, "a seed phrase has {} words, not {}", PHRASE_WORDS, count)
+            }
+            WalletError::UnknownWord(ref word) => write!(f, "unknown word '{}'", word),
+            WalletError::Checksum => write!(f, "the seed phrase's checksum doesn't match"),
+        }
+    }
+}
+
+// What's written to the wallet file. The keys follow from the phrase, so they don't
+// need to be stored.
+#[derive(Serialize, Deserialize)]
+struct WalletFile {
+    phrase: String,
+    next_index: u32,
+}
+
+// The wallet as shared between the http handlers and the light node's sync thread
+pub type SharedWallet = Arc<RwLock<Wallet>>;
+
+// A wallet derives all of its keypairs from a single seed phrase, so backing up the
+// phrase is enough to recover every address it ever handed out
+pub struct Wallet {
+    entropy: Vec<u8>,
+
+    // Root of the key tree
+    seed: [u8; sign::SEEDBYTES],
+
+    // Index of the next receiving address to hand out
+    next_index: u32,
+
+    // Where the wallet is saved, if anywhere
+    path: Option<PathBuf>,
+}
+
+impl Wallet {
+    // Creates a wallet with a new random seed phrase
+    pub fn generate() -> Wallet {
+        Wallet::from_entropy(randombytes(ENTROPY_BYTES))
+    }
+
+    // Restores a wallet from its seed phrase. Only the first address is known
+    // afterwards, use `recover` to find the others.
+    pub fn from_phrase(phrase: &str) -> Result<Wallet, WalletError> {
+        let words: Vec<&str> = phrase.split_whitespace().collect();
+        if words.len() != PHRASE_WORDS {
+            return Err(WalletError::WordCount(words.len()));
+        }
+
+        let mut bytes = Vec::with_capacity(PHRASE_WORDS);
+        for word in words {
+            let word = word.to_lowercase();
+            match WORDS.iter().position(|&known| known == word) {
+                Some(byte) => bytes.push(byte as u8),
+                None => return Err(WalletError::UnknownWord(word)),
+            }
+        }
+
+        let checksum = bytes.pop().unwrap();
+        if checksum != Wallet::checksum(&bytes) {
+            return Err(WalletError::Checksum);
+        }
+
+        Ok(Wallet::from_entropy(bytes))
+    }
+
+    fn from_entropy(entropy: Vec<u8>) -> Wallet {
+        let mut data = SEED_DOMAIN.to_vec();
+        data.extend_from_slice(&entropy);
+        let sha256::Digest(seed) = sha256::hash(&data);
+
+        Wallet {
+            entropy,
+            seed,
+            next_index: 1,
+            path: None,
+        }
+    }
+
+    fn checksum(entropy: &[u8]) -> u8 {
+        let sha256::Digest(digest) = sha256::hash(entropy);
+
+        digest[0]
+    }
+
+    // Loads the wallet saved at the path, or creates and saves a new one if there is
+    // none. Returns whether the wallet was created.
+    pub fn open(path: PathBuf) -> Result<(Wallet, bool), WalletError> {
+        if path.exists() {
+            let file = File::open(&path).map_err(WalletError::Io)?;
+            let wallet_file: WalletFile =
+                serde_json::from_reader(file).map_err(WalletError::Parse)?;
+
+            let mut wallet = Wallet::from_phrase(&wallet_file.phrase)?;
+            wallet.next_index = wallet_file.next_index;
+            wallet.path = Some(path);
+
+            return Ok((wallet, false));
+        }
+
+        let mut wallet = Wallet::generate();
+        wallet.path = Some(path);
+        wallet.persist()?;
+
+        Ok((wallet, true))
+    }
+
+    // Saves the wallet at the given path from now on
+    pub fn set_path(&mut self, path: PathBuf) {
+        self.path = Some(path);
+    }
+
+    // Writes the wallet to its file, if it has one
+    pub fn persist(&self) -> Result<(), WalletError> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        let wallet_file = WalletFile {
+            phrase: self.phrase(),
+            next_index: self.next_index,
+        };
+
+        // Written to a temporary file first, so a crash mid-write leaves the previous
+        // wallet intact
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        {
+            let file = create_private(&tmp_path).map_err(WalletError::Io)?;
+            serde_json::to_writer_pretty(file, &wallet_file).map_err(WalletError::Parse)?;
+        }
+
+        fs::rename(tmp_path, path).map_err(WalletError::Io)
+    }
+
+    pub fn phrase(&self) -> String {
+        let mut bytes = self.entropy.clone();
+        bytes.push(Wallet::checksum(&self.entropy));
+
+        let words: Vec<&str> = bytes.iter().map(|&byte| WORDS[byte as usize]).collect();
+        words.join(" ")
+    }
+
+    // Derives the keypair at a path in the key tree. Every level hashes the seed of
+    // its parent together with the index, so the whole tree follows from the root.
+    pub fn derive(&self, path: &[u32]) -> Address {
+        let mut seed = self.seed;
+
+        for &index in path {
+            let mut data = seed.to_vec();
+            data.extend_from_slice(&[
+                (index >> 24) as u8,
+                (index >> 16) as u8,
+                (index >> 8) as u8,
+                index as u8,
+            ]);
+
+            let sha256::Digest(child) = sha256::hash(&data);
+            seed = child;
+        }
+
+        Address::from_seed(&sign::Seed(seed))
+    }
+
+    // The receiving address at the given index. The first one is the wallet's
+    // main address.
+    pub fn address(&self, index: u32) -> Address {
+        self.derive(&[RECEIVING_BRANCH, index])
+    }
+
+    // Hands out a receiving address that hasn't been handed out before
+    pub fn new_address(&mut self) -> Address {
+        let address = self.address(self.next_index);
+        self.next_index += 1;
+
+        address
+    }
+
+    // All receiving addresses handed out so far
+    pub fn addresses(&self) -> Vec<Address> {
+        (0..self.next_index)
+            .map(|index| self.address(index))
+            .collect()
+    }
+
+    // Finds the receiving addresses that were handed out before the wallet was
+    // restored, by looking for addresses that have been used. The search stops
+    // after a gap of unused addresses.
+    pub fn recover<F: Fn(&PublicKey) -> bool>(&mut self, is_used: F) {
+        let mut index = 0;
+        let mut unused = 0;
+
+        while unused < RECOVERY_GAP {
+            if is_used(&self.address(index).public_key) {
+                self.next_index = max(self.next_index, index + 1);
+                unused = 0;
+            } else {
+                unused += 1;
+            }
+
+            index += 1;
+        }
+    }
+}
+
+// Creates a file that only its owner can read, since the wallet file holds the seed
+// phrase. A file left over from an earlier attempt is replaced, so that it doesn't
+// keep its permissions.
+#[cfg(unix)]
+fn create_private(path: &Path) -> io::Result<File> {
+    remove_leftover(path)?;
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &Path) -> io::Result<File> {
+    remove_leftover(path)?;
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+fn remove_leftover(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
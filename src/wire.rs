@@ -0,0 +1,294 @@
+use super::blockchain::{Block, BlockHeader, SharedChain};
+use super::sync::{self, MAX_BLOCKS, MAX_HEADERS};
+use super::transaction::Transaction;
+
+use bincode::{deserialize, serialize};
+use log::Level;
+use std::cmp::min;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+// Version of the peer protocol spoken by this node, and the oldest one it still
+// understands
+pub const PROTOCOL_VERSION: u32 = 2;
+pub const MIN_PROTOCOL_VERSION: u32 = 2;
+
+// Every frame starts with the network's magic bytes, followed by the length of
+// the payload
+pub type Magic = [u8; 4];
+const FRAME_HEADER_LEN: usize = 8;
+
+// Upper bound on payloads, so that a peer can't make us allocate arbitrary amounts
+const MAX_PAYLOAD_LEN: usize = 32 * 1024 * 1024;
+
+const CONNECT_TIMEOUT_SECS: u64 = 5;
+
+// How long a peer may go without sending anything before it's disconnected
+const IDLE_TIMEOUT_SECS: u64 = 60;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum Message {
+    // Opens the handshake. Both sides send one, then acknowledge the other's.
+    Version {
+        version: u32,
+        height: usize,
+        listen_port: Option<u16>,
+    },
+    Verack,
+
+    GetHeaders {
+        from: usize,
+        count: usize,
+    },
+    Headers(Vec<BlockHeader>),
+    GetBlocks {
+        from: usize,
+        count: usize,
+    },
+    Blocks(Vec<Block>),
+
+    // Announcements, which aren't answered
+    Transaction(Transaction),
+    NewBlock(Block),
+}
+
+#[derive(Debug)]
+pub enum WireError {
+    Io(io::Error),
+    // The peer closed the connection between two messages
+    Closed,
+    // The frame didn't start with our magic bytes, so the peer belongs to another
+    // network
+    BadMagic,
+    // The frame announced a payload larger than we accept
+    TooLarge(usize),
+    // The payload couldn't be decoded into a message
+    Decode,
+    // The peer speaks a protocol version we no longer understand
+    IncompatibleVersion(u32),
+    // The peer sent a message that doesn't fit the conversation
+    Unexpected,
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WireError::Io(ref err) => write!(f, "{}", err),
+            WireError::Closed => write!(f, "connection closed"),
+            WireError::BadMagic => write!(f, "bad magic bytes"),
+            WireError::TooLarge(len) => write!(f, "payload of {} bytes is too large", len),
+            WireError::Decode => write!(f, "couldn't decode message"),
+            WireError::IncompatibleVersion(version) => {
+                write!(f, "incompatible protocol version {}", version)
+            }
+            WireError::Unexpected => write!(f, "unexpected message"),
+        }
+    }
+}
+
+impl From<io::Error> for WireError {
+    fn from(err: io::Error) -> WireError {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            WireError::Closed
+        } else {
+            WireError::Io(err)
+        }
+    }
+}
+
+// Writes a message as a single frame: magic bytes, the payload length as a
+// big-endian u32, and the bincode encoded payload
+pub fn write_message<W: Write>(
+    writer: &mut W,
+    magic: Magic,
+    message: &Message,
+) -> Result<(), WireError> {
+    let payload = serialize(message).map_err(|_| WireError::Decode)?;
+    if payload.len() > MAX_PAYLOAD_LEN {
+        return Err(WireError::TooLarge(payload.len()));
+    }
+
+    let len = payload.len() as u32;
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+    frame.extend_from_slice(&magic);
+    frame.extend_from_slice(&[
+        (len >> 24) as u8,
+        (len >> 16) as u8,
+        (len >> 8) as u8,
+        len as u8,
+    ]);
+    frame.extend_from_slice(&payload);
+
+    writer.write_all(&frame)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+pub fn read_message<R: Read>(reader: &mut R, magic: Magic) -> Result<Message, WireError> {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    reader.read_exact(&mut header)?;
+
+    if header[0..4] != magic {
+        return Err(WireError::BadMagic);
+    }
+
+    let len = header[4..8]
+        .iter()
+        .fold(0usize, |len, &byte| (len << 8) | byte as usize);
+    if len > MAX_PAYLOAD_LEN {
+        return Err(WireError::TooLarge(len));
+    }
+
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload)?;
+
+    deserialize(&payload).map_err(|_| WireError::Decode)
+}
+
+fn version_message(height: usize, listen_port: Option<u16>) -> Message {
+    Message::Version {
+        version: PROTOCOL_VERSION,
+        height,
+        listen_port,
+    }
+}
+
+fn check_version(message: Message) -> Result<(), WireError> {
+    match message {
+        Message::Version { version, .. } => {
+            if version < MIN_PROTOCOL_VERSION {
+                Err(WireError::IncompatibleVersion(version))
+            } else {
+                Ok(())
+            }
+        }
+        _ => Err(WireError::Unexpected),
+    }
+}
+
+fn expect_verack(message: Message) -> Result<(), WireError> {
+    match message {
+        Message::Verack => Ok(()),
+        _ => Err(WireError::Unexpected),
+    }
+}
+
+// Opens a connection to a peer and performs the handshake
+pub fn connect(
+    addr: &str,
+    magic: Magic,
+    height: usize,
+    listen_port: Option<u16>,
+) -> Result<TcpStream, WireError> {
+    let mut stream = TcpStream::connect(addr)?;
+    stream.set_read_timeout(Some(Duration::from_secs(CONNECT_TIMEOUT_SECS)))?;
+
+    write_message(&mut stream, magic, &version_message(height, listen_port))?;
+    check_version(read_message(&mut stream, magic)?)?;
+    write_message(&mut stream, magic, &Message::Verack)?;
+    expect_verack(read_message(&mut stream, magic)?)?;
+
+    Ok(stream)
+}
+
+// Asks a peer for headers or blocks over a connection of its own, and returns the
+// answer
+pub fn request(addr: &str, magic: Magic, message: &Message) -> Result<Message, WireError> {
+    let mut stream = connect(addr, magic, 0, None)?;
+    write_message(&mut stream, magic, message)?;
+
+    read_message(&mut stream, magic)
+}
+
+// Sends an announcement to every peer in the background, one short-lived connection
+// per peer
+pub fn announce(
+    peers: Vec<String>,
+    magic: Magic,
+    height: usize,
+    listen_port: Option<u16>,
+    message: Message,
+) {
+    thread::spawn(move || {
+        for peer in peers {
+            let result = connect(&peer, magic, height, listen_port)
+                .and_then(|mut stream| write_message(&mut stream, magic, &message));
+
+            if let Err(err) = result {
+                warn!("Couldn't announce to peer {}: {}", peer, err);
+            }
+        }
+    });
+}
+
+// Serves peer connections on the listener, each on its own thread
+pub fn serve(listener: TcpListener, chain: SharedChain) {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let chain = chain.clone();
+                    let peer = stream
+                        .peer_addr()
+                        .map(|addr| addr.to_string())
+                        .unwrap_or_default();
+                    log_event!(Level::Debug, "Peer connected", peer = peer);
+
+                    thread::spawn(move || match handle_connection(stream, chain) {
+                        Ok(()) => log_event!(Level::Debug, "Peer disconnected", peer = peer),
+                        Err(err) => {
+                            log_event!(Level::Info, "Peer dropped", peer = peer, reason = err)
+                        }
+                    });
+                }
+                Err(err) => warn!("Couldn't accept peer connection: {}", err),
+            }
+        }
+    });
+}
+
+fn handle_connection(mut stream: TcpStream, chain: SharedChain) -> Result<(), WireError> {
+    // Idle peers are dropped rather than keeping their thread around
+    stream.set_read_timeout(Some(Duration::from_secs(IDLE_TIMEOUT_SECS)))?;
+
+    let (magic, height, listen_port) = {
+        let reader = chain.read().unwrap();
+        (reader.spec.magic, reader.chain.len(), reader.p2p_port)
+    };
+
+    check_version(read_message(&mut stream, magic)?)?;
+    write_message(&mut stream, magic, &version_message(height, listen_port))?;
+    write_message(&mut stream, magic, &Message::Verack)?;
+    expect_verack(read_message(&mut stream, magic)?)?;
+
+    loop {
+        let message = match read_message(&mut stream, magic) {
+            Ok(message) => message,
+            Err(WireError::Closed) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        match message {
+            Message::GetHeaders { from, count } => {
+                let headers = chain.read().unwrap().headers(from, min(count, MAX_HEADERS));
+                write_message(&mut stream, magic, &Message::Headers(headers))?;
+            }
+            Message::GetBlocks { from, count } => {
+                let blocks = chain.read().unwrap().blocks(from, min(count, MAX_BLOCKS));
+                write_message(&mut stream, magic, &Message::Blocks(blocks))?;
+            }
+            // Whatever is turned down was logged already, and peers aren't told
+            Message::Transaction(transaction) => {
+                let _ = sync::receive_transaction(&chain, transaction);
+            }
+            Message::NewBlock(block) => {
+                let _ = sync::receive_block(&chain, block);
+            }
+            _ => return Err(WireError::Unexpected),
+        }
+    }
+}
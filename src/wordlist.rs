@@ -0,0 +1,32 @@
+// Words used to encode wallet seed phrases. Each word stands for one byte, so
+// there are exactly 256 of them.
+pub const WORDS: [&str; 256] = [
+    "acid", "acorn", "actor", "adapt", "agent", "alarm", "album", "alley", "amber", "angle",
+    "ankle", "apple", "april", "arena", "armor", "arrow", "atlas", "attic", "audio", "autumn",
+    "avocado", "badge", "bagel", "baker", "bamboo", "banana", "banjo", "barrel", "basil", "basket",
+    "beacon", "bean", "beaver", "bench", "berry", "bicycle", "bishop", "blanket", "blossom",
+    "boat", "bonus", "border", "bottle", "bracket", "breeze", "brick", "bridge", "bronze", "brush",
+    "bubble", "bucket", "buffalo", "cabin", "cactus", "camel", "candle", "canoe", "canvas",
+    "carbon", "carpet", "castle", "cedar", "cello", "cement", "chalk", "cherry", "chimney",
+    "cider", "cinema", "circus", "clover", "cobalt", "cobra", "coconut", "comet", "copper",
+    "coral", "cotton", "cradle", "crystal", "cupboard", "dagger", "daisy", "delta", "desert",
+    "diamond", "dolphin", "donkey", "dragon", "drum", "eagle", "echo", "elbow", "ember", "engine",
+    "falafel", "falcon", "feather", "fence", "ferry", "fiddle", "finch", "flute", "fossil",
+    "fountain", "fox", "garden", "garlic", "gazelle", "ginger", "glacier", "globe", "goblet",
+    "gravel", "guitar", "hammer", "harbor", "harp", "hazel", "helmet", "heron", "hollow", "honey",
+    "horizon", "iceberg", "igloo", "island", "ivory", "jacket", "jaguar", "jasmine", "jelly",
+    "jigsaw", "jungle", "kayak", "kettle", "kiwi", "ladder", "lagoon", "lantern", "laurel",
+    "lemon", "lily", "linen", "lizard", "lobster", "locket", "lotus", "lumber", "magnet", "mango",
+    "maple", "marble", "meadow", "melon", "mirror", "mitten", "monkey", "mosaic", "muffin",
+    "mustard", "nectar", "needle", "nickel", "noodle", "nutmeg", "oasis", "ocean", "olive",
+    "onion", "orbit", "orchid", "otter", "oyster", "paddle", "palace", "panda", "paper", "parrot",
+    "peach", "pebble", "pepper", "piano", "pickle", "pigeon", "pillow", "pine", "planet", "plum",
+    "pocket", "pony", "potato", "pumpkin", "puzzle", "quartz", "quilt", "rabbit", "radish",
+    "raven", "ribbon", "river", "robin", "rocket", "saddle", "salmon", "sandal", "scarf", "shadow",
+    "shell", "silver", "sketch", "sleeve", "socket", "spider", "spoon", "squid", "stable", "stone",
+    "summer", "sunset", "swan", "tablet", "tango", "teapot", "temple", "thistle", "thunder",
+    "tiger", "timber", "toast", "tomato", "torch", "trumpet", "tulip", "tunnel", "turtle",
+    "umbrella", "valley", "velvet", "violin", "volcano", "wagon", "walnut", "walrus", "wheat",
+    "whistle", "willow", "window", "winter", "wizard", "yacht", "yodel", "yogurt", "zebra",
+    "zephyr", "zipper",
+];